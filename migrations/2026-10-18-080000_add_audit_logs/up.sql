@@ -0,0 +1,25 @@
+CREATE TABLE audit_logs (
+	id BIGSERIAL PRIMARY KEY NOT NULL,
+	customer_id uuid,
+	actor TEXT NOT NULL,
+	action TEXT NOT NULL,
+	target TEXT,
+	ip TEXT,
+	user_agent TEXT,
+	success BOOLEAN NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_logs_customer_id ON audit_logs(customer_id, created_at);
+CREATE INDEX audit_logs_action ON audit_logs(action);
+CREATE INDEX audit_logs_created_at ON audit_logs(created_at);
+
+-- The audit log is append-only, refuse any modification of existing rows
+CREATE OR REPLACE FUNCTION audit_logs_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_logs is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_logs_append_only BEFORE UPDATE OR DELETE ON audit_logs
+    FOR EACH ROW EXECUTE PROCEDURE audit_logs_append_only();
@@ -0,0 +1,6 @@
+DROP TRIGGER IF EXISTS apikeys_default_org_id ON apikeys;
+DROP FUNCTION IF EXISTS apikeys_default_org_id();
+ALTER TABLE apikeys DROP COLUMN org_id;
+DROP TABLE org_invitations;
+DROP TABLE org_members;
+DROP TABLE organizations;
\ No newline at end of file
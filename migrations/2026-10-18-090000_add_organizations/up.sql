@@ -0,0 +1,56 @@
+CREATE TABLE organizations (
+	id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+	name VARCHAR(255) NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE org_members (
+	org_id uuid NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+	customer_id uuid NOT NULL REFERENCES customers(id) ON DELETE CASCADE,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	PRIMARY KEY (org_id, customer_id)
+);
+
+CREATE INDEX org_members_customer_id ON org_members(customer_id);
+
+CREATE TABLE org_invitations (
+	id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+	org_id uuid NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+	email VARCHAR(255) NOT NULL,
+	invited_by uuid NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	UNIQUE (org_id, email)
+);
+
+-- Each existing customer get a personal organization (sharing his id)
+-- which take the ownership of his current keys.
+INSERT INTO organizations (id, name) SELECT id, email FROM customers;
+INSERT INTO org_members (org_id, customer_id) SELECT id, id FROM customers;
+
+-- The keys whose customer no longer exists get one too (without member),
+-- so that every key has an organization before adding the constraint.
+INSERT INTO organizations (id, name)
+	SELECT DISTINCT customer_id, customer_id::text FROM apikeys
+	WHERE customer_id NOT IN (SELECT id FROM organizations);
+
+ALTER TABLE apikeys ADD COLUMN org_id uuid;
+UPDATE apikeys SET org_id = customer_id;
+ALTER TABLE apikeys ALTER COLUMN org_id SET NOT NULL;
+ALTER TABLE apikeys ADD CONSTRAINT apikeys_org_id_fkey
+	FOREIGN KEY (org_id) REFERENCES organizations(id) ON DELETE CASCADE;
+
+CREATE INDEX apikeys_org_id ON apikeys(org_id);
+
+-- The services inserting keys without knowing the organizations (sproot's
+-- ApiKeyDTO) give them to the personal organization of their creator.
+CREATE OR REPLACE FUNCTION apikeys_default_org_id() RETURNS trigger AS $$
+BEGIN
+	IF NEW.org_id IS NULL THEN
+		NEW.org_id := NEW.customer_id;
+	END IF;
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER apikeys_default_org_id BEFORE INSERT ON apikeys
+	FOR EACH ROW EXECUTE PROCEDURE apikeys_default_org_id();
@@ -0,0 +1 @@
+ALTER TABLE org_members DROP COLUMN role;
\ No newline at end of file
@@ -0,0 +1,7 @@
+ALTER TABLE org_members ADD COLUMN role TEXT NOT NULL DEFAULT 'member'
+	CHECK (role IN ('owner', 'admin', 'member', 'readonly'));
+
+-- The first member of each organization is its creator, and so its owner
+UPDATE org_members SET role = 'owner' WHERE (org_id, created_at) IN (
+	SELECT org_id, MIN(created_at) FROM org_members GROUP BY org_id
+);
@@ -0,0 +1,3 @@
+DROP INDEX org_invitations_org_id;
+ALTER TABLE org_invitations DROP COLUMN expires_at;
+ALTER TABLE org_invitations DROP COLUMN role;
\ No newline at end of file
@@ -0,0 +1,5 @@
+ALTER TABLE org_invitations ADD COLUMN role TEXT NOT NULL DEFAULT 'member'
+	CHECK (role IN ('owner', 'admin', 'member', 'readonly'));
+ALTER TABLE org_invitations ADD COLUMN expires_at TIMESTAMP NOT NULL DEFAULT NOW() + INTERVAL '7 days';
+
+CREATE INDEX org_invitations_org_id ON org_invitations(org_id);
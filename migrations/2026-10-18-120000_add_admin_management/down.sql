@@ -0,0 +1,3 @@
+DROP TABLE bertas;
+DROP TABLE customer_sessions;
+ALTER TABLE customers DROP COLUMN plan;
\ No newline at end of file
@@ -0,0 +1,22 @@
+ALTER TABLE customers ADD COLUMN plan TEXT NOT NULL DEFAULT 'free';
+
+CREATE TABLE customer_sessions (
+	id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+	customer_id uuid NOT NULL REFERENCES customers(id) ON DELETE CASCADE,
+	ip TEXT,
+	user_agent TEXT,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	revoked_at TIMESTAMP
+);
+
+CREATE INDEX customer_sessions_customer_id ON customer_sessions(customer_id);
+
+CREATE TABLE bertas (
+	name TEXT PRIMARY KEY NOT NULL,
+	enabled BOOLEAN NOT NULL DEFAULT true,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- B1 was the only berta used until now
+INSERT INTO bertas (name) VALUES ('B1');
+INSERT INTO bertas (name) SELECT DISTINCT berta FROM apikeys ON CONFLICT DO NOTHING;
@@ -0,0 +1,5 @@
+ALTER TABLE apikeys DROP COLUMN disabled_at;
+
+ALTER TABLE customers DROP COLUMN suspended_at;
+ALTER TABLE customers DROP COLUMN status_changed_at;
+ALTER TABLE customers DROP COLUMN status;
@@ -0,0 +1,8 @@
+ALTER TABLE customers ADD COLUMN status TEXT NOT NULL DEFAULT 'active'
+	CONSTRAINT customers_status_check
+	CHECK (status IN ('active', 'pending-deletion'));
+ALTER TABLE customers ADD COLUMN status_changed_at TIMESTAMP;
+-- Tracked apart from the status so that a suspension doesn't cancel a pending deletion
+ALTER TABLE customers ADD COLUMN suspended_at TIMESTAMP;
+
+ALTER TABLE apikeys ADD COLUMN disabled_at TIMESTAMP;
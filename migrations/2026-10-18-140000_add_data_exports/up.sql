@@ -0,0 +1,16 @@
+CREATE TABLE data_exports (
+	id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+	customer_id uuid NOT NULL REFERENCES customers(id) ON DELETE CASCADE,
+	status TEXT NOT NULL DEFAULT 'pending'
+		CHECK (status IN ('pending', 'ready', 'failed')),
+	archive BYTEA,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	expires_at TIMESTAMP NOT NULL,
+	-- Set when a worker starts generating the export, which is picked up
+	-- again if still pending long after (the worker having stopped).
+	claimed_at TIMESTAMP
+);
+
+CREATE INDEX data_exports_customer_id ON data_exports(customer_id);
+CREATE INDEX data_exports_expires_at ON data_exports(expires_at);
+CREATE INDEX data_exports_pending ON data_exports(created_at) WHERE status = 'pending';
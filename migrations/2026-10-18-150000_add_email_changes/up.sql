@@ -0,0 +1,8 @@
+-- Pending change of email, the customer can only have one at a time
+CREATE TABLE email_changes (
+	id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+	customer_id uuid NOT NULL UNIQUE REFERENCES customers(id) ON DELETE CASCADE,
+	new_email VARCHAR(255) NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	expires_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Fixed window counters shared by all the workers (and instances)
+CREATE TABLE rate_limits (
+	key TEXT PRIMARY KEY NOT NULL,
+	window_start TIMESTAMP NOT NULL DEFAULT NOW(),
+	count INT4 NOT NULL DEFAULT 1
+);
+
+CREATE INDEX rate_limits_window_start ON rate_limits(window_start);
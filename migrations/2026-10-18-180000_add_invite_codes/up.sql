@@ -0,0 +1,9 @@
+-- Codes generated by the admins to sign up when the registration is invite-only
+CREATE TABLE invite_codes (
+	code TEXT PRIMARY KEY NOT NULL,
+	created_by TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	expires_at TIMESTAMP,
+	max_uses INT4 NOT NULL DEFAULT 1,
+	uses INT4 NOT NULL DEFAULT 0
+);
@@ -0,0 +1,19 @@
+-- Outgoing mails, delivered (and retried) by the mail queue worker.
+-- The bodies are removed once sent (or expired) as they contain the links,
+-- the mails not delivered before expires_at being dead-lettered.
+CREATE TABLE mail_outbox (
+	id UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+	recipient TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	plain TEXT,
+	html TEXT,
+	status TEXT NOT NULL DEFAULT 'pending',
+	attempts INT4 NOT NULL DEFAULT 0,
+	next_attempt_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	last_error TEXT,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	sent_at TIMESTAMP,
+	expires_at TIMESTAMP
+);
+
+CREATE INDEX mail_outbox_due ON mail_outbox(next_attempt_at) WHERE status = 'pending';
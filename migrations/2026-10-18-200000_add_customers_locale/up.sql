@@ -0,0 +1,2 @@
+-- Preferred language tag of the customer for the mails (eg: fr, en-GB)
+ALTER TABLE customers ADD COLUMN locale TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE revoke_links;
+ALTER TABLE customers DROP COLUMN notification_optouts;
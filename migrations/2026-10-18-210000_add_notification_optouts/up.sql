@@ -0,0 +1,15 @@
+-- Kinds of the (non-critical) security notifications the customer opted out of
+ALTER TABLE customers ADD COLUMN notification_optouts TEXT[] NOT NULL DEFAULT '{}';
+
+-- "This wasn't me" links of the security notifications, each one usable once.
+-- The link of an email change restores the previous email.
+CREATE TABLE revoke_links (
+	id UUID PRIMARY KEY NOT NULL DEFAULT gen_random_uuid(),
+	customer_id UUID NOT NULL REFERENCES customers(id) ON DELETE CASCADE,
+	previous_email TEXT,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+	expires_at TIMESTAMP NOT NULL,
+	used_at TIMESTAMP
+);
+
+CREATE INDEX revoke_links_customer_id ON revoke_links(customer_id);
@@ -0,0 +1,4 @@
+ALTER TABLE customers DROP COLUMN deliverability_changed_at;
+ALTER TABLE customers DROP COLUMN deliverability_reason;
+ALTER TABLE customers DROP CONSTRAINT customers_deliverability_check;
+ALTER TABLE customers DROP COLUMN deliverability;
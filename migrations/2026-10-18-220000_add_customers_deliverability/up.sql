@@ -0,0 +1,7 @@
+-- Deliverability of the customer's email, as reported by the bounces
+-- and complaints received from the mail provider
+ALTER TABLE customers ADD COLUMN deliverability TEXT NOT NULL DEFAULT 'ok';
+ALTER TABLE customers ADD CONSTRAINT customers_deliverability_check
+	CHECK (deliverability IN ('ok', 'soft-bounce', 'complaint', 'hard-bounce'));
+ALTER TABLE customers ADD COLUMN deliverability_reason TEXT;
+ALTER TABLE customers ADD COLUMN deliverability_changed_at TIMESTAMP;
@@ -0,0 +1,9 @@
+DROP FUNCTION pseudonymize_audit_logs(uuid, TEXT);
+
+CREATE OR REPLACE FUNCTION audit_logs_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_logs is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TABLE audit_erasures;
@@ -0,0 +1,73 @@
+-- Customers whose entries are being pseudonymised, only listed for the time
+-- of the transaction running pseudonymize_audit_logs
+CREATE TABLE audit_erasures (
+	customer_id uuid PRIMARY KEY NOT NULL,
+	email TEXT NOT NULL
+);
+REVOKE ALL ON audit_erasures FROM PUBLIC;
+
+-- The audit log stays append-only, except for the pseudonymisation of the
+-- entries of a customer listed in audit_erasures: clearing the ip and user
+-- agent of his entries, and replacing his email by exactly 'deleted:<id>'.
+CREATE OR REPLACE FUNCTION audit_logs_append_only() RETURNS trigger AS $$
+DECLARE
+	erased audit_erasures%ROWTYPE;
+	pseudonym TEXT;
+	owned BOOLEAN;
+BEGIN
+	IF TG_OP = 'UPDATE'
+		AND NEW.id = OLD.id
+		AND NEW.customer_id IS NOT DISTINCT FROM OLD.customer_id
+		AND NEW.action = OLD.action
+		AND NEW.success = OLD.success
+		AND NEW.created_at = OLD.created_at
+	THEN
+		FOR erased IN SELECT * FROM audit_erasures LOOP
+			pseudonym := 'deleted:' || erased.customer_id;
+			owned := COALESCE(OLD.customer_id = erased.customer_id, FALSE)
+				OR OLD.actor = erased.customer_id::text
+				OR lower(OLD.actor) = lower(erased.email);
+
+			IF (NEW.ip IS NOT DISTINCT FROM OLD.ip OR (owned AND NEW.ip IS NULL))
+				AND (NEW.user_agent IS NOT DISTINCT FROM OLD.user_agent
+					OR (owned AND NEW.user_agent IS NULL))
+				AND (NEW.actor = OLD.actor
+					OR (lower(OLD.actor) = lower(erased.email) AND NEW.actor = pseudonym))
+				AND (NEW.target IS NOT DISTINCT FROM OLD.target
+					OR (owned AND OLD.action LIKE 'account.email.%' AND NEW.target = pseudonym)
+					OR NEW.target = replace(OLD.target, erased.email, pseudonym))
+			THEN
+				RETURN NEW;
+			END IF;
+		END LOOP;
+	END IF;
+
+	RAISE EXCEPTION 'audit_logs is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+-- Pseudonymise the entries of the deleted customer, his emails as the target
+-- of the others' entries (eg: invitations) included. Return the number of
+-- entries updated.
+CREATE FUNCTION pseudonymize_audit_logs(erased_id uuid, erased_email TEXT) RETURNS BIGINT AS $$
+DECLARE
+	pseudonym TEXT := 'deleted:' || erased_id;
+	owned_count BIGINT;
+	targeted_count BIGINT;
+BEGIN
+	INSERT INTO audit_erasures (customer_id, email) VALUES (erased_id, erased_email);
+
+	UPDATE audit_logs SET ip = NULL, user_agent = NULL,
+		actor = CASE WHEN lower(actor) = lower(erased_email) THEN pseudonym ELSE actor END,
+		target = CASE WHEN action LIKE 'account.email.%' THEN pseudonym ELSE target END
+	WHERE customer_id = erased_id OR actor = erased_id::text OR lower(actor) = lower(erased_email);
+	GET DIAGNOSTICS owned_count = ROW_COUNT;
+
+	UPDATE audit_logs SET target = replace(target, erased_email, pseudonym)
+	WHERE strpos(target, erased_email) > 0;
+	GET DIAGNOSTICS targeted_count = ROW_COUNT;
+
+	DELETE FROM audit_erasures WHERE customer_id = erased_id;
+	RETURN owned_count + targeted_count;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER SET search_path = pg_catalog, public;
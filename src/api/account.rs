@@ -0,0 +1,728 @@
+use actix_session::Session;
+use actix_web::{
+    http::header::{self, ContentType},
+    web, HttpRequest, HttpResponse,
+};
+use base64::Engine;
+use chrono::{Duration, NaiveDateTime};
+use diesel::Connection;
+use lettre::message::Mailbox;
+use serde::{Deserialize, Serialize};
+use sproot::{apierrors::ApiError, models::AuthPool};
+use uuid::Uuid;
+
+use super::{EmailSso, JwtToken, Specific};
+use crate::{
+    api::{extract_mailbox, get_user_session},
+    models::{
+        email_taken_error, Account, AccountStatus, AuditLog, CustomerExport, CustomerSession,
+        DataExport, Deliverability, EmailChange, EmailChangeDTO, OrgMember, RevokeLink,
+    },
+    utils::{
+        audit::{AuditAction, AuditEvent, RequestMeta},
+        domain_policy::check_signup_domain,
+        email::email_domain,
+        i18n::{is_language_tag, Locale},
+        jwt::{self, Purpose},
+        mail_deletion::{send_deletion_mail, DELETION_LINK_MINUTES},
+        mail_email_change::{send_email_confirm_mail, send_email_notice_mail, EMAIL_CHANGE_HOURS},
+        notifications::{notify, NotificationKind, SecurityEvent},
+    },
+    CONFIG,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeletionSchedule {
+    pub deletion_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingExport {
+    pub id: Uuid,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LocalePreference {
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeliverabilityState {
+    pub state: Deliverability,
+    pub reason: Option<String>,
+    pub changed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    pub optouts: Vec<NotificationKind>,
+}
+
+/// Export generated right away or left to a background task
+enum ExportOutcome {
+    Archive(Vec<u8>),
+    Deferred(PendingExport),
+}
+
+/// Decode the (base64 encoded) token issued for the purpose,
+/// and return the id of the email change it refers to.
+fn decode_email_change(purpose: Purpose, jwt: &str) -> Result<Uuid, ApiError> {
+    let change_id = match base64::prelude::BASE64_STANDARD.decode(jwt) {
+        Ok(decoded) => jwt::decode_purpose_jwt(
+            purpose,
+            std::str::from_utf8(&decoded).map_err(|_| ApiError::AuthorizationError(None))?,
+        )?,
+        Err(_) => return Err(ApiError::AuthorizationError(None)),
+    };
+
+    Ok(Uuid::parse_str(&change_id)?)
+}
+
+/// Response carrying the JSON archive as a file to download
+fn export_attachment(archive: Vec<u8>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"speculare-export.json\"",
+        ))
+        .body(archive)
+}
+
+/// POST /api/account/delete
+///
+/// Request the deletion of the account of the currently logged user,
+/// which must be confirmed using the link sent by mail. Refused if the
+/// user is the last owner of an organization shared with other members.
+pub async fn request_deletion(
+    request: HttpRequest,
+    session: Session,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/delete");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<(), ApiError> {
+        let conn = &mut dbc.pool.get()?;
+        if !OrgMember::get_last_owned_orgs(conn, &user_uuid)?.is_empty() {
+            return Err(ApiError::InvalidRequestError(Some(
+                "org_ownership_transfer_required".to_owned(),
+            )));
+        }
+
+        let account = Account::get(conn, &user_uuid)?;
+        let mailboxed: Mailbox = account
+            .email
+            .parse()
+            .map_err(|_| ApiError::ServerError(None))?;
+
+        // Create the signed deletion token, base64 encoded for convenience
+        let jwt = jwt::create_purpose_jwt(
+            Purpose::Deletion,
+            &user_uuid.to_string(),
+            Duration::minutes(DELETION_LINK_MINUTES),
+        )?;
+        let encoded = base64::prelude::BASE64_STANDARD.encode(jwt);
+        send_deletion_mail(conn, mailboxed, account.locale(), &encoded)
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::AccountDeleteRequest, user_uuid.to_string())
+        .customer(user_uuid)
+        .record(&db, &meta, res.is_ok())
+        .await;
+    res?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// POST /api/account/delete/confirm?jwt
+///
+/// Confirm the deletion using the token received by mail, the account
+/// is then pending deletion until the end of the grace period.
+pub async fn confirm_deletion(
+    request: HttpRequest,
+    session: Session,
+    db: web::Data<AuthPool>,
+    jwt_holder: web::Query<JwtToken>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/delete/confirm");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<DeletionSchedule, ApiError> {
+        // Get the customer_id from the jwt token
+        let customer_id = match base64::prelude::BASE64_STANDARD.decode(&jwt_holder.jwt) {
+            Ok(decoded) => jwt::decode_purpose_jwt(
+                Purpose::Deletion,
+                std::str::from_utf8(&decoded).map_err(|_| ApiError::AuthorizationError(None))?,
+            )?,
+            Err(_) => return Err(ApiError::AuthorizationError(None)),
+        };
+
+        // The token must have been issued to the logged user
+        if Uuid::parse_str(&customer_id)? != user_uuid {
+            return Err(ApiError::AuthorizationError(None));
+        }
+
+        // Don't postpone an already scheduled deletion
+        let conn = &mut dbc.pool.get()?;
+        let updated = Account::transition_status(
+            conn,
+            &user_uuid,
+            AccountStatus::Active,
+            AccountStatus::PendingDeletion,
+        )?;
+        if updated == 0 {
+            return Err(ApiError::InvalidRequestError(None));
+        }
+
+        // Computed from the database clock, the one of the deletion job
+        let scheduled_at = Account::get(conn, &user_uuid)?
+            .status_changed_at
+            .ok_or(ApiError::ServerError(None))?;
+        Ok(DeletionSchedule {
+            deletion_at: scheduled_at + Duration::days(CONFIG.deletion_grace_days),
+        })
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::AccountDeleteConfirm, user_uuid.to_string())
+        .customer(user_uuid)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().json(res?))
+}
+
+/// POST /api/account/delete/cancel
+///
+/// Cancel the pending deletion of the account of the currently logged user
+pub async fn cancel_deletion(
+    request: HttpRequest,
+    session: Session,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/delete/cancel");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<(), ApiError> {
+        let updated = Account::transition_status(
+            &mut dbc.pool.get()?,
+            &user_uuid,
+            AccountStatus::PendingDeletion,
+            AccountStatus::Active,
+        )?;
+        match updated {
+            0 => Err(ApiError::InvalidRequestError(None)),
+            _ => Ok(()),
+        }
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::AccountDeleteCancel, user_uuid.to_string())
+        .customer(user_uuid)
+        .record(&db, &meta, res.is_ok())
+        .await;
+    res?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// POST /api/account/export
+///
+/// Export everything known about the currently logged user as JSON.
+/// Small accounts get the archive directly, for the larger ones the
+/// export is generated in the background and the link sent by mail
+/// (202 Accepted with the id of the export).
+pub async fn request_export(
+    request: HttpRequest,
+    session: Session,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/export");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<ExportOutcome, ApiError> {
+        let conn = &mut dbc.pool.get()?;
+        if AuditLog::count_by_customer(conn, &user_uuid)? <= CONFIG.export_sync_max_events {
+            let archive = CustomerExport::collect(conn, &user_uuid)?.to_json()?;
+            return Ok(ExportOutcome::Archive(archive.into_bytes()));
+        }
+
+        // Generated by the export job, which mails the link once it's ready
+        let export = DataExport::create(conn, &user_uuid, CONFIG.export_validity_hours)?;
+        Ok(ExportOutcome::Deferred(PendingExport {
+            id: export.id,
+            expires_at: export.expires_at,
+        }))
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::AccountExport, user_uuid.to_string())
+        .customer(user_uuid)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    match res? {
+        ExportOutcome::Archive(archive) => Ok(export_attachment(archive)),
+        ExportOutcome::Deferred(pending) => Ok(HttpResponse::Accepted().json(pending)),
+    }
+}
+
+/// GET /api/account/export?uuid
+///
+/// Download the export (generated in the background) of the currently
+/// logged user, if it's ready and not expired.
+pub async fn download_export(
+    request: HttpRequest,
+    session: Session,
+    db: web::Data<AuthPool>,
+    info: web::Query<Specific>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/account/export");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let meta = RequestMeta::from_request(&request);
+    let export_uuid = Uuid::parse_str(&info.uuid)?;
+
+    let dbc = db.clone();
+    let res = web::block(move || {
+        match DataExport::get_ready_archive(&mut dbc.pool.get()?, &export_uuid, &user_uuid)? {
+            Some(archive) => Ok(archive),
+            None => Err(ApiError::InvalidRequestError(None)),
+        }
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::AccountExportDownload, user_uuid.to_string())
+        .customer(user_uuid)
+        .target(export_uuid.to_string())
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(export_attachment(res?))
+}
+
+/// POST /api/account/email
+///
+/// Request the change of the email of the currently logged user.
+/// A confirmation link is sent to the new address and a notice
+/// with a link to cancel the change is sent to the current one.
+pub async fn request_email_change(
+    request: HttpRequest,
+    session: Session,
+    db: web::Data<AuthPool>,
+    wemail: web::Json<EmailSso>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/email");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<(), ApiError> {
+        let (new_email, new_mailboxed) = extract_mailbox(wemail.into_inner())?;
+        let conn = &mut dbc.pool.get()?;
+        let account = Account::get(conn, &user_uuid)?;
+        if account.email == new_email {
+            return Err(ApiError::InvalidRequestError(None));
+        }
+        // Moving to a domain refused at the sign up would bypass the policy
+        check_signup_domain(email_domain(&new_email))?;
+        // Checked again when the change is confirmed
+        if matches!(Account::get_by_email(conn, &new_email)?, Some(other) if other.id != user_uuid)
+        {
+            return Err(email_taken_error());
+        }
+        let old_mailboxed: Mailbox = account
+            .email
+            .parse()
+            .map_err(|_| ApiError::ServerError(None))?;
+
+        let validity = Duration::hours(EMAIL_CHANGE_HOURS);
+        let change = EmailChange::upsert(
+            conn,
+            &EmailChangeDTO {
+                customer_id: user_uuid,
+                new_email: &new_email,
+            },
+            EMAIL_CHANGE_HOURS,
+        )?;
+
+        // Distinct tokens so the old address can't confirm the change
+        let confirm_jwt =
+            jwt::create_purpose_jwt(Purpose::EmailConfirm, &change.id.to_string(), validity)?;
+        let cancel_jwt =
+            jwt::create_purpose_jwt(Purpose::EmailCancel, &change.id.to_string(), validity)?;
+
+        send_email_confirm_mail(
+            conn,
+            new_mailboxed,
+            account.locale(),
+            &change.expires_at,
+            &base64::prelude::BASE64_STANDARD.encode(confirm_jwt),
+        )?;
+        send_email_notice_mail(
+            conn,
+            old_mailboxed,
+            account.locale(),
+            &new_email,
+            &change.expires_at,
+            &base64::prelude::BASE64_STANDARD.encode(cancel_jwt),
+        )
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::AccountEmailRequest, user_uuid.to_string())
+        .customer(user_uuid)
+        .record(&db, &meta, res.is_ok())
+        .await;
+    res?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// POST /api/account/email/confirm?jwt
+///
+/// Confirm the new email using the token sent to it and update the account,
+/// the previous email being notified of the change.
+pub async fn confirm_email_change(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    jwt_holder: web::Query<JwtToken>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/email/confirm");
+
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<(EmailChange, String), ApiError> {
+        let change_id = decode_email_change(Purpose::EmailConfirm, &jwt_holder.jwt)?;
+        let conn = &mut dbc.pool.get()?;
+        // A cancelled (or replaced) change no longer exists
+        let change = EmailChange::get_pending(conn, &change_id)?
+            .ok_or(ApiError::AuthorizationError(None))?;
+
+        let old_email = Account::get(conn, &change.customer_id)?.email;
+        change.apply(conn)?;
+        Ok((change, old_email))
+    })
+    .await?;
+
+    let (change, old_email) = match res {
+        Ok(confirmed) => confirmed,
+        Err(err) => {
+            AuditEvent::new(AuditAction::AccountEmailChange, "anonymous")
+                .record(&db, &meta, false)
+                .await;
+            return Err(err);
+        }
+    };
+
+    AuditEvent::new(
+        AuditAction::AccountEmailChange,
+        change.customer_id.to_string(),
+    )
+    .customer(change.customer_id)
+    .target(change.new_email.to_owned())
+    .record(&db, &meta, true)
+    .await;
+
+    let event = SecurityEvent::EmailChange {
+        old_email,
+        new_email: change.new_email,
+    };
+    notify(&db, change.customer_id, event, &meta).await;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// POST /api/account/email/cancel?jwt
+///
+/// Cancel the pending email change using the token sent to the old email
+pub async fn cancel_email_change(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    jwt_holder: web::Query<JwtToken>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/email/cancel");
+
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<EmailChange, ApiError> {
+        let change_id = decode_email_change(Purpose::EmailCancel, &jwt_holder.jwt)?;
+        let conn = &mut dbc.pool.get()?;
+        let change = match EmailChange::get(conn, &change_id)? {
+            Some(change) => change,
+            None => return Err(ApiError::InvalidRequestError(None)),
+        };
+
+        EmailChange::delete(conn, &change.id)?;
+        Ok(change)
+    })
+    .await?;
+
+    let change = match res {
+        Ok(change) => change,
+        Err(err) => {
+            AuditEvent::new(AuditAction::AccountEmailCancel, "anonymous")
+                .record(&db, &meta, false)
+                .await;
+            return Err(err);
+        }
+    };
+
+    AuditEvent::new(
+        AuditAction::AccountEmailCancel,
+        change.customer_id.to_string(),
+    )
+    .customer(change.customer_id)
+    .target(change.new_email)
+    .record(&db, &meta, true)
+    .await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// POST /api/account/locale
+///
+/// Set the preferred language tag (eg: fr, en-GB) of the mails, or reset it
+/// to the default_locale with null. Return the locale actually used as the
+/// untranslated languages fall back to the default_locale then to english.
+pub async fn set_locale(
+    request: HttpRequest,
+    session: Session,
+    db: web::Data<AuthPool>,
+    info: web::Json<LocalePreference>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/locale");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let meta = RequestMeta::from_request(&request);
+    let locale = info.into_inner().locale;
+
+    if matches!(&locale, Some(tag) if !is_language_tag(tag)) {
+        return Err(ApiError::InvalidRequestError(None));
+    }
+
+    let dbc = db.clone();
+    let target = locale.to_owned().unwrap_or_default();
+    let res = web::block(move || {
+        Account::set_locale(&mut dbc.pool.get()?, &user_uuid, locale.as_deref())?;
+        Ok::<_, ApiError>(Locale::resolve(locale.as_deref()))
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::AccountLocaleChange, user_uuid.to_string())
+        .customer(user_uuid)
+        .target(target)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().body(res?.as_str()))
+}
+
+/// GET /api/account/notifications
+///
+/// Get the kinds of security notifications the currently logged user opted out of
+pub async fn get_notifications(
+    session: Session,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/account/notifications");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+
+    let account = web::block(move || Account::get(&mut db.pool.get()?, &user_uuid)).await??;
+    // Ignore the kinds no longer known by the ssot
+    let optouts = account
+        .notification_optouts
+        .iter()
+        .filter_map(|kind| kind.parse().ok())
+        .collect();
+
+    Ok(HttpResponse::Ok().json(NotificationPreferences { optouts }))
+}
+
+/// POST /api/account/notifications
+///
+/// Replace the kinds of security notifications the currently logged
+/// user opted out of. The critical ones (email-change) can't be.
+pub async fn set_notifications(
+    request: HttpRequest,
+    session: Session,
+    db: web::Data<AuthPool>,
+    info: web::Json<NotificationPreferences>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/notifications");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let meta = RequestMeta::from_request(&request);
+    let optouts = info.into_inner().optouts;
+
+    if optouts.iter().any(NotificationKind::is_critical) {
+        return Err(ApiError::InvalidRequestError(Some(
+            "notification_critical".to_owned(),
+        )));
+    }
+
+    let dbc = db.clone();
+    let target = optouts
+        .iter()
+        .map(NotificationKind::as_str)
+        .collect::<Vec<_>>()
+        .join(",");
+    let res = web::block(move || {
+        Account::set_notification_optouts(&mut dbc.pool.get()?, &user_uuid, &optouts)
+    })
+    .await?;
+
+    AuditEvent::new(
+        AuditAction::AccountNotificationsChange,
+        user_uuid.to_string(),
+    )
+    .customer(user_uuid)
+    .target(target)
+    .record(&db, &meta, res.is_ok())
+    .await;
+    res?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// POST /api/account/sessions/revoke?jwt
+///
+/// Revoke all the sessions of the customer using the "this wasn't me"
+/// link of the security notifications, no session being required. The link
+/// of an email change also restores the previous email, or suspends the
+/// account until an admin reviews it if that email has been taken since.
+/// Each link can only be used once.
+pub async fn revoke_sessions(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    jwt_holder: web::Query<JwtToken>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/sessions/revoke");
+
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<(Uuid, Option<&'static str>), ApiError> {
+        let link_id = match base64::prelude::BASE64_STANDARD.decode(&jwt_holder.jwt) {
+            Ok(decoded) => jwt::decode_purpose_jwt(
+                Purpose::Revoke,
+                std::str::from_utf8(&decoded).map_err(|_| ApiError::AuthorizationError(None))?,
+            )?,
+            Err(_) => return Err(ApiError::AuthorizationError(None)),
+        };
+        let link_id = Uuid::parse_str(&link_id)?;
+
+        let conn = &mut dbc.pool.get()?;
+        conn.transaction(|conn| {
+            let link = match RevokeLink::consume(conn, &link_id)? {
+                Some(link) => link,
+                None => return Err(ApiError::AuthorizationError(None)),
+            };
+
+            CustomerSession::revoke_all(conn, &link.customer_id)?;
+            let outcome = match &link.previous_email {
+                None => None,
+                Some(previous) if EmailChange::revert(conn, &link.customer_id, previous)? => {
+                    Some("email-restored")
+                }
+                Some(_) => {
+                    Account::suspend(conn, &link.customer_id, false)?;
+                    Some("suspended")
+                }
+            };
+            Ok((link.customer_id, outcome))
+        })
+    })
+    .await?;
+
+    let (customer_id, outcome) = match res {
+        Ok(revoked) => revoked,
+        Err(err) => {
+            AuditEvent::new(AuditAction::AccountSessionsRevoke, "anonymous")
+                .record(&db, &meta, false)
+                .await;
+            return Err(err);
+        }
+    };
+
+    let mut event = AuditEvent::new(AuditAction::AccountSessionsRevoke, customer_id.to_string())
+        .customer(customer_id);
+    if let Some(outcome) = outcome {
+        event = event.target(outcome);
+    }
+    event.record(&db, &meta, true).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// GET /api/account/deliverability
+///
+/// Get the deliverability of the email of the currently logged user, as
+/// reported by the bounces and complaints. A hard bounced email only gets
+/// a magic link every bounce_retry_hours, the user should fix it (or change
+/// it) then reset the state.
+pub async fn get_deliverability(
+    session: Session,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/account/deliverability");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+
+    let account = web::block(move || Account::get(&mut db.pool.get()?, &user_uuid)).await??;
+
+    Ok(HttpResponse::Ok().json(DeliverabilityState {
+        state: account.deliverability(),
+        reason: account.deliverability_reason,
+        changed_at: account.deliverability_changed_at,
+    }))
+}
+
+/// POST /api/account/deliverability/reset
+///
+/// Mark the email of the currently logged user as deliverable again
+pub async fn reset_deliverability(
+    request: HttpRequest,
+    session: Session,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/account/deliverability/reset");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || {
+        Account::set_deliverability(&mut dbc.pool.get()?, &user_uuid, Deliverability::Ok, None)
+    })
+    .await?;
+
+    AuditEvent::new(
+        AuditAction::AccountDeliverabilityReset,
+        user_uuid.to_string(),
+    )
+    .customer(user_uuid)
+    .record(&db, &meta, res.is_ok())
+    .await;
+    res?;
+
+    Ok(HttpResponse::Ok().finish())
+}
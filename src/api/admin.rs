@@ -0,0 +1,688 @@
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use lettre::message::Mailbox;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sproot::{apierrors::ApiError, models::AuthPool};
+use uuid::Uuid;
+
+use super::{Paged, Specific};
+use crate::{
+    api::check_admin,
+    models::{
+        Account, AuditFilter, AuditLog, Berta, BertaDTO, CustomerSession, Deliverability,
+        InviteCode, InviteCodeDTO, OrgApiKey, QueuedMail,
+    },
+    utils::{
+        audit::{AuditAction, AuditEvent, RequestMeta},
+        i18n::Locale,
+        mail_preview::{render_sample, MailTemplate},
+        mailer::send_now,
+        notifications::NotificationKind,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CustomerSearch {
+    pub q: Option<String>,
+    pub deliverability: Option<Deliverability>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyFilter {
+    pub customer_id: Option<Uuid>,
+    pub org_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusUpdate {
+    pub uuid: String,
+    pub with_keys: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlanUpdate {
+    pub customer_id: Uuid,
+    pub plan: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BertaName {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InviteCodeRequest {
+    pub max_uses: Option<i32>,
+    pub validity_hours: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InviteCodeValue {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MailPreview {
+    pub template: MailTemplate,
+    pub locale: Option<String>,
+    /// Event of the security notification (new-login by default)
+    pub kind: Option<NotificationKind>,
+    /// Return the html alone, to be opened in a browser
+    pub html: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MailTestSend {
+    pub template: MailTemplate,
+    pub locale: Option<String>,
+    pub kind: Option<NotificationKind>,
+    pub email: String,
+}
+
+/// Audit event performed by the admin (name)
+fn admin_event(action: AuditAction, admin: &str) -> AuditEvent {
+    AuditEvent::new(action, format!("admin:{}", admin))
+}
+
+/// GET /admin/audit?customer_id&action&actor&since&until&size&page
+///
+/// Query the audit events across all customers
+pub async fn get_audit_logs(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    filter: web::Query<AuditFilter>,
+    info: web::Query<Paged>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /admin/audit");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let (size, page) = info.get();
+
+    let dbc = db.clone();
+    let res =
+        web::block(move || AuditLog::search(&mut dbc.pool.get()?, &filter, size, page)).await?;
+
+    admin_event(AuditAction::AdminAuditQuery, &admin)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().json(res?))
+}
+
+/// GET /admin/customers?q&deliverability&size&page
+///
+/// Search the customers by (part of) their email, optionally
+/// only those whose email is in the deliverability state.
+pub async fn search_customers(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    search: web::Query<CustomerSearch>,
+    info: web::Query<Paged>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /admin/customers");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let (size, page) = info.get();
+    let search = search.into_inner();
+    let pattern = search.q.unwrap_or_default();
+    let deliverability = search.deliverability;
+
+    let dbc = db.clone();
+    let target = pattern.to_owned();
+    let res = web::block(move || {
+        Account::search(&mut dbc.pool.get()?, &pattern, deliverability, size, page)
+    })
+    .await?;
+
+    admin_event(AuditAction::AdminCustomerSearch, &admin)
+        .target(target)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().json(res?))
+}
+
+/// Suspend (or reactivate) the customer (uuid) and record it,
+/// the keys are disabled (or enabled) too if with_keys is set.
+async fn set_customer_suspended(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<StatusUpdate>,
+    suspended: bool,
+) -> Result<HttpResponse, ApiError> {
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let customer_uuid = Uuid::parse_str(&info.uuid)?;
+    let with_keys = info.with_keys.unwrap_or(false);
+
+    let dbc = db.clone();
+    let res = web::block(move || {
+        let conn = &mut dbc.pool.get()?;
+        if suspended {
+            Account::suspend(conn, &customer_uuid, with_keys)
+        } else {
+            Account::unsuspend(conn, &customer_uuid, with_keys)
+        }
+    })
+    .await?;
+
+    let action = if suspended {
+        AuditAction::AdminCustomerSuspend
+    } else {
+        AuditAction::AdminCustomerUnsuspend
+    };
+    admin_event(action, &admin)
+        .customer(customer_uuid)
+        .target(customer_uuid.to_string())
+        .record(&db, &meta, matches!(res, Ok(1)))
+        .await;
+
+    match res? {
+        0 => Err(ApiError::InvalidRequestError(None)),
+        _ => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+/// POST /admin/customer/suspend?uuid&with_keys
+///
+/// Suspend the customer, who can no longer log in, and revoke
+/// all his sessions. If with_keys is true, his keys are disabled.
+pub async fn suspend_customer(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<StatusUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /admin/customer/suspend");
+
+    set_customer_suspended(request, db, info, true).await
+}
+
+/// POST /admin/customer/unsuspend?uuid&with_keys
+///
+/// Reactivate the suspended customer, refused if he isn't suspended (a
+/// pending deletion isn't cancelled). If with_keys is true, his keys are enabled.
+pub async fn unsuspend_customer(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<StatusUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /admin/customer/unsuspend");
+
+    set_customer_suspended(request, db, info, false).await
+}
+
+/// POST /admin/customer/logout?uuid
+///
+/// Revoke all the sessions of the customer.
+/// Return the number of sessions revoked.
+pub async fn logout_customer(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<Specific>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /admin/customer/logout");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let customer_uuid = Uuid::parse_str(&info.uuid)?;
+
+    let dbc = db.clone();
+    let res = web::block(move || CustomerSession::revoke_all(&mut dbc.pool.get()?, &customer_uuid))
+        .await?;
+
+    admin_event(AuditAction::AdminForceLogout, &admin)
+        .customer(customer_uuid)
+        .target(customer_uuid.to_string())
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().body(res?.to_string()))
+}
+
+/// POST /admin/customer/deliverability?uuid
+///
+/// Mark the email of the customer as deliverable again, allowing the
+/// magic links to be sent to an email which hard bounced.
+pub async fn reset_deliverability(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<Specific>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /admin/customer/deliverability");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let customer_uuid = Uuid::parse_str(&info.uuid)?;
+
+    let dbc = db.clone();
+    let res = web::block(move || {
+        Account::set_deliverability(
+            &mut dbc.pool.get()?,
+            &customer_uuid,
+            Deliverability::Ok,
+            None,
+        )
+    })
+    .await?;
+
+    admin_event(AuditAction::AdminDeliverabilityReset, &admin)
+        .customer(customer_uuid)
+        .target(customer_uuid.to_string())
+        .record(&db, &meta, matches!(res, Ok(1)))
+        .await;
+
+    match res? {
+        0 => Err(ApiError::InvalidRequestError(None)),
+        _ => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+/// POST /admin/customer/plan
+///
+/// Change the plan of the customer
+pub async fn update_plan(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Json<PlanUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /admin/customer/plan");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let PlanUpdate { customer_id, plan } = info.into_inner();
+
+    let plan = plan.trim().to_owned();
+    if plan.is_empty() || plan.len() > 64 {
+        return Err(ApiError::InvalidRequestError(None));
+    }
+
+    let dbc = db.clone();
+    let target = plan.to_owned();
+    let res =
+        web::block(move || Account::set_plan(&mut dbc.pool.get()?, &customer_id, &plan)).await?;
+
+    admin_event(AuditAction::AdminPlanChange, &admin)
+        .customer(customer_id)
+        .target(target)
+        .record(&db, &meta, matches!(res, Ok(1)))
+        .await;
+
+    match res? {
+        0 => Err(ApiError::InvalidRequestError(None)),
+        _ => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+/// GET /admin/keys?customer_id&org_id&size&page
+///
+/// List the keys (redacted) across all customers
+pub async fn get_keys(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    filter: web::Query<KeyFilter>,
+    info: web::Query<Paged>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /admin/keys");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let (size, page) = info.get();
+
+    let dbc = db.clone();
+    let res = web::block(move || {
+        OrgApiKey::search(
+            &mut dbc.pool.get()?,
+            filter.customer_id,
+            filter.org_id,
+            size,
+            page,
+        )
+    })
+    .await?;
+
+    admin_event(AuditAction::AdminKeyList, &admin)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    let data: Vec<OrgApiKey> = res?.into_iter().map(OrgApiKey::redacted).collect();
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// GET /admin/berta/list
+pub async fn get_bertas(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /admin/berta/list");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || Berta::get_all(&mut dbc.pool.get()?)).await?;
+
+    admin_event(AuditAction::AdminBertaList, &admin)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().json(res?))
+}
+
+/// POST /admin/berta
+///
+/// Add a berta or enable/disable an existing one. The new keys
+/// are assigned to the enabled berta with the fewest keys.
+pub async fn post_berta(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Json<BertaDTO>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /admin/berta");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let entry = info.into_inner();
+
+    if entry.name.trim().is_empty() {
+        return Err(ApiError::InvalidRequestError(None));
+    }
+
+    let dbc = db.clone();
+    let target = format!("{}:{}", entry.name, entry.enabled);
+    let res = web::block(move || Berta::upsert(&mut dbc.pool.get()?, &entry)).await?;
+
+    admin_event(AuditAction::AdminBertaUpdate, &admin)
+        .target(target)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().json(res?))
+}
+
+/// DELETE /admin/berta?name
+///
+/// Remove the berta from the ones available for the new keys,
+/// the existing keys are left untouched.
+pub async fn delete_berta(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<BertaName>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route DELETE /admin/berta");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let name = info.into_inner().name;
+
+    let dbc = db.clone();
+    let target = name.to_owned();
+    let res = web::block(move || Berta::delete(&mut dbc.pool.get()?, &name)).await?;
+
+    admin_event(AuditAction::AdminBertaDelete, &admin)
+        .target(target)
+        .record(&db, &meta, matches!(res, Ok(1)))
+        .await;
+
+    match res? {
+        0 => Err(ApiError::InvalidRequestError(None)),
+        _ => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+/// GET /admin/invite-code/list
+///
+/// Get all the invite codes, with their remaining uses
+pub async fn get_invite_codes(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /admin/invite-code/list");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || InviteCode::get_all(&mut dbc.pool.get()?)).await?;
+
+    admin_event(AuditAction::AdminInviteCodeList, &admin)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().json(res?))
+}
+
+/// POST /admin/invite-code
+///
+/// Generate an invite code to sign up when the registration is
+/// invite-only, usable once and forever unless specified otherwise.
+pub async fn post_invite_code(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Json<InviteCodeRequest>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /admin/invite-code");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let info = info.into_inner();
+
+    let max_uses = info.max_uses.unwrap_or(1);
+    if max_uses < 1 || matches!(info.validity_hours, Some(hours) if hours < 1) {
+        return Err(ApiError::InvalidRequestError(None));
+    }
+    let code: String = thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(24)
+        .map(char::from)
+        .collect();
+
+    let dbc = db.clone();
+    let target = code.to_owned();
+    let created_by = admin.to_owned();
+    let res = web::block(move || {
+        InviteCode::insert_and_get(
+            &mut dbc.pool.get()?,
+            &InviteCodeDTO {
+                code: &code,
+                created_by: &created_by,
+                validity_hours: info.validity_hours,
+                max_uses,
+            },
+        )
+    })
+    .await?;
+
+    admin_event(AuditAction::AdminInviteCodeCreate, &admin)
+        .target(target)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().json(res?))
+}
+
+/// DELETE /admin/invite-code?code
+///
+/// Revoke an invite code, the accounts created with it are kept
+pub async fn delete_invite_code(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<InviteCodeValue>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route DELETE /admin/invite-code");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let code = info.into_inner().code;
+
+    let dbc = db.clone();
+    let target = code.to_owned();
+    let res = web::block(move || InviteCode::delete(&mut dbc.pool.get()?, &code)).await?;
+
+    admin_event(AuditAction::AdminInviteCodeDelete, &admin)
+        .target(target)
+        .record(&db, &meta, matches!(res, Ok(1)))
+        .await;
+
+    match res? {
+        0 => Err(ApiError::InvalidRequestError(None)),
+        _ => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+/// GET /admin/mail/stuck?size&page
+///
+/// Get the mails which failed to be delivered at least once,
+/// including the dead-lettered ones (without their content)
+pub async fn get_stuck_mails(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<Paged>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /admin/mail/stuck");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let (size, page) = info.get();
+
+    let dbc = db.clone();
+    let res = web::block(move || QueuedMail::get_stuck(&mut dbc.pool.get()?, size, page)).await?;
+
+    admin_event(AuditAction::AdminMailList, &admin)
+        .record(&db, &meta, res.is_ok())
+        .await;
+
+    Ok(HttpResponse::Ok().json(res?))
+}
+
+/// POST /admin/mail/retry?uuid
+///
+/// Put a mail not yet sent back in the queue, resetting its attempts,
+/// refused once it's dead-lettered as its content is removed
+pub async fn retry_mail(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<Specific>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /admin/mail/retry");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let mail_uuid = Uuid::parse_str(&info.uuid)?;
+
+    let dbc = db.clone();
+    let res = web::block(move || QueuedMail::retry(&mut dbc.pool.get()?, &mail_uuid)).await?;
+
+    admin_event(AuditAction::AdminMailRetry, &admin)
+        .target(mail_uuid.to_string())
+        .record(&db, &meta, matches!(res, Ok(1)))
+        .await;
+
+    match res? {
+        0 => Err(ApiError::InvalidRequestError(None)),
+        _ => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+/// DELETE /admin/mail?uuid
+///
+/// Drop a queued mail, sent or not
+pub async fn delete_mail(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<Specific>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route DELETE /admin/mail");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let mail_uuid = Uuid::parse_str(&info.uuid)?;
+
+    let dbc = db.clone();
+    let res = web::block(move || QueuedMail::delete(&mut dbc.pool.get()?, &mail_uuid)).await?;
+
+    admin_event(AuditAction::AdminMailDelete, &admin)
+        .target(mail_uuid.to_string())
+        .record(&db, &meta, matches!(res, Ok(1)))
+        .await;
+
+    match res? {
+        0 => Err(ApiError::InvalidRequestError(None)),
+        _ => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+/// GET /admin/mail/preview?template&locale&kind&html
+///
+/// Render the mail template with sample values, as JSON (subject,
+/// plain and html) or as the html alone if html is set.
+pub async fn preview_mail(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<MailPreview>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /admin/mail/preview");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let info = info.into_inner();
+    let locale = Locale::resolve(info.locale.as_deref());
+    let kind = info.kind.unwrap_or(NotificationKind::NewLogin);
+
+    let res = web::block(move || render_sample(info.template, locale, kind)).await?;
+
+    admin_event(AuditAction::AdminMailPreview, &admin)
+        .target(info.template.as_str())
+        .record(&db, &meta, res.is_ok())
+        .await;
+    let mail = res?;
+
+    match mail.html {
+        Some(html) if info.html.unwrap_or(false) => Ok(HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(html)),
+        _ => Ok(HttpResponse::Ok().json(mail)),
+    }
+}
+
+/// POST /admin/mail/preview
+///
+/// Render the mail template with sample values and send it right away to
+/// the email through the configured transport, bypassing the mail queue.
+pub async fn send_test_mail(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Json<MailTestSend>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /admin/mail/preview");
+
+    let admin = check_admin(&request)?;
+    let meta = RequestMeta::from_request(&request);
+    let info = info.into_inner();
+    let locale = Locale::resolve(info.locale.as_deref());
+    let kind = info.kind.unwrap_or(NotificationKind::NewLogin);
+    let target = format!("{}:{}", info.template.as_str(), info.email);
+
+    let res = web::block(move || {
+        let mailboxed: Mailbox = info
+            .email
+            .parse()
+            .map_err(|_| ApiError::InvalidRequestError(None))?;
+        let mail = render_sample(info.template, locale, kind)?;
+
+        send_now(mailboxed, &mail).map_err(|err| {
+            error!("MAILER: cannot send the test mail: {}", err);
+            ApiError::ServerError(Some("mail_transport_failed".to_owned()))
+        })
+    })
+    .await?;
+
+    admin_event(AuditAction::AdminMailTest, &admin)
+        .target(target)
+        .record(&db, &meta, res.is_ok())
+        .await;
+    res?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -5,11 +5,23 @@ use sproot::{
     apierrors::ApiError,
     models::{ApiKey, ApiKeyDTO, AuthPool, BaseCrud, DtoBase},
 };
+use uuid::Uuid;
 
 use super::{Specific, SpecificKey};
-use crate::api::{get_header_value, get_user_session};
+use crate::{
+    api::{get_active_org, get_header_value, get_user_session},
+    models::{Berta, OrgApiKey, OrgApiKeyDTO, OrgMember, Permission},
+    utils::{
+        audit::{AuditAction, AuditEvent, RequestMeta},
+        notifications::{notify, SecurityEvent},
+        rate_limit::{check_limits, RateLimit},
+    },
+    CONFIG,
+};
 
 /// GET /api/key?id
+///
+/// Require the KeyRead permission in the active organization.
 pub async fn get_apikey(
     session: Session,
     db: web::Data<AuthPool>,
@@ -17,10 +29,13 @@ pub async fn get_apikey(
 ) -> Result<HttpResponse, ApiError> {
     info!("Route GET /api/key");
 
-    let user_uuid = get_user_session(&session)?;
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
 
     let data = web::block(move || {
-        ApiKey::get_by_keyid_and_owner(&mut db.pool.get()?, &user_uuid, info.id)
+        let conn = &mut db.pool.get()?;
+        OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::KeyRead)?;
+        OrgApiKey::get_by_id_and_org(conn, &org_uuid, info.id)
     })
     .await??;
 
@@ -28,15 +43,23 @@ pub async fn get_apikey(
 }
 
 /// GET /api/key/list
+///
+/// Require the KeyRead permission in the active organization.
 pub async fn get_apikeys(
     session: Session,
     db: web::Data<AuthPool>,
 ) -> Result<HttpResponse, ApiError> {
     info!("Route GET /api/key/list");
 
-    let user_uuid = get_user_session(&session)?;
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
 
-    let data = web::block(move || ApiKey::get(&mut db.pool.get()?, &user_uuid, 100, 0)).await??;
+    let data = web::block(move || {
+        let conn = &mut db.pool.get()?;
+        OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::KeyRead)?;
+        OrgApiKey::get_by_org(conn, &org_uuid, 100, 0)
+    })
+    .await??;
 
     Ok(HttpResponse::Ok().json(data))
 }
@@ -46,6 +69,10 @@ pub async fn get_apikeys(
 /// This route update the host_uuid of the ApiKey entry
 /// with key == sptk if the host_uuid was previously None.
 /// The host_uuid is took from the Specific query params (?uuid=)
+///
+/// The key belongs to its organization, whatever became of its creator
+/// (his keys are handed over when he leaves), who is notified by mail
+/// of the binding.
 pub async fn update_apikey(
     request: HttpRequest,
     db: web::Data<AuthPool>,
@@ -54,17 +81,24 @@ pub async fn update_apikey(
     info!("Route PATCH /api/key");
 
     let sptk = get_header_value(&request, "SPTK")?;
+    let meta = RequestMeta::from_request(&request);
+    let host_uuid = info.uuid.to_owned();
 
-    web::block(move || {
-        // Get the key which have the key == sptk
-        let api_key = ApiKey::get_by_key(&mut db.pool.get()?, sptk.to_str().unwrap())?;
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<(i64, Uuid, bool), ApiError> {
+        let conn = &mut dbc.pool.get()?;
+        // Get the key which have the key == sptk (not disabled)
+        let sptk = sptk
+            .to_str()
+            .map_err(|_| ApiError::AuthorizationError(None))?;
+        let api_key = OrgApiKey::get_usable_by_key(conn, sptk)?;
 
         // If the host_uuid of that key is none, we update the value with the
         // current host_uuid from Specific otherwise it's an error as the user
         // try to update a Key that doesn't belong to him.
         if api_key.host_uuid.is_none() {
             ApiKey::update(
-                &mut db.pool.get()?,
+                conn,
                 &api_key.key,
                 &ApiKeyDTO {
                     host_uuid: Some(info.uuid.to_owned()),
@@ -72,55 +106,125 @@ pub async fn update_apikey(
                 },
             )?;
 
-            Ok(())
+            Ok((api_key.id, api_key.customer_id, true))
         } else {
-            Err(ApiError::AuthorizationError(None))
+            Ok((api_key.id, api_key.customer_id, false))
         }
     })
-    .await??;
+    .await?;
+
+    // Record both the successful binding and the attempts to rebind a key
+    let mut event = AuditEvent::new(AuditAction::ApiKeyBind, format!("host:{}", host_uuid));
+    if let Ok((key_id, owner, _)) = &res {
+        event = event.customer(*owner).target(key_id.to_string());
+    }
+    event
+        .record(&db, &meta, matches!(res, Ok((_, _, true))))
+        .await;
 
+    let (key_id, owner, bound) = res?;
+    if !bound {
+        return Err(ApiError::AuthorizationError(None));
+    }
+
+    let event = SecurityEvent::HostBind { key_id, host_uuid };
+    notify(&db, owner, event, &meta).await;
     Ok(HttpResponse::Ok().finish())
 }
 
+/// GET /api/key/introspect
+///
+/// Return the ApiKey entry with key == sptk if it can be used,
+/// refuse disabled keys and keys created by a suspended customer.
+pub async fn introspect_apikey(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/key/introspect");
+
+    let sptk = get_header_value(&request, "SPTK")?;
+
+    let data = web::block(move || {
+        let conn = &mut db.pool.get()?;
+        let sptk = sptk
+            .to_str()
+            .map_err(|_| ApiError::AuthorizationError(None))?;
+        let api_key = OrgApiKey::get_usable_by_key(conn, sptk)?;
+
+        Ok::<_, ApiError>(api_key.redacted())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(data))
+}
+
 /// POST /api/key
 ///
-/// Create a new ApiKey for the currently logged user (inner_user).
+/// Create a new ApiKey for the active organization of the currently
+/// logged user (inner_user), the user being recorded as its creator.
 /// The resulting ApiKey is returned back via Json.
 /// We'll also do the check for the quota of the user here,
 /// depending on his plan, we'll allow him to create (or not)
-/// a new ApiKey.
+/// a new ApiKey. Require the KeyCreate permission.
+/// The user is notified by mail of the creation.
 pub async fn post_apikey(
     session: Session,
+    request: HttpRequest,
     db: web::Data<AuthPool>,
 ) -> Result<HttpResponse, ApiError> {
     info!("Route POST /api/key");
 
-    let user_uuid = get_user_session(&session)?;
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
+    let meta = RequestMeta::from_request(&request);
+
+    let limit = RateLimit::new(
+        "key",
+        "customer",
+        user_uuid.to_string(),
+        CONFIG.rate_limits.key_create_per_customer,
+    );
+    if let Some(limited) = check_limits(&db, vec![limit]).await? {
+        return Ok(limited.response());
+    }
 
     // TODO - Add check that the user can in fact create
     //        the key (based on his plan subscriptions)
 
     // Insert/get the inserted key
-    let data = web::block(move || {
-        ApiKey::insert_and_get(
-            &mut db.pool.get()?,
-            &ApiKeyDTO {
-                key: Some(
-                    thread_rng()
-                        .sample_iter(&rand::distributions::Alphanumeric)
-                        .take(32)
-                        .map(char::from)
-                        .collect(),
-                ),
+    let dbc = db.clone();
+    let res = web::block(move || {
+        let conn = &mut dbc.pool.get()?;
+        OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::KeyCreate)?;
+        // Assign the key to the berta with the lowest occupation
+        let berta = Berta::least_occupied(conn)?;
+        OrgApiKey::insert_and_get(
+            conn,
+            &OrgApiKeyDTO {
+                key: thread_rng()
+                    .sample_iter(&rand::distributions::Alphanumeric)
+                    .take(32)
+                    .map(char::from)
+                    .collect(),
                 host_uuid: None,
-                customer_id: Some(user_uuid),
-                // TODO - Make the berta selection based on occupation
-                berta: Some("B1".to_owned()),
+                customer_id: user_uuid,
+                berta,
+                org_id: org_uuid,
             },
         )
     })
-    .await??;
+    .await?;
+
+    let mut event =
+        AuditEvent::new(AuditAction::ApiKeyCreate, user_uuid.to_string()).customer(user_uuid);
+    if let Ok(data) = &res {
+        event = event.target(data.id.to_string());
+    }
+    event.record(&db, &meta, res.is_ok()).await;
+    let data = res?;
 
+    let event = SecurityEvent::KeyCreate { key_id: data.id };
+    notify(&db, user_uuid, event, &meta).await;
     Ok(HttpResponse::Ok().json(data))
 }
 
@@ -128,7 +232,10 @@ pub async fn post_apikey(
 ///
 /// Delete an ApiKey with the key == SPTK.
 /// Check if the ApiKey matching the SPTK is owned by the
-/// the currently logged user (inner_user).
+/// active organization of the currently logged user (inner_user)
+/// and that he holds the KeyDelete permission.
+/// The creator of the key and the owners of the organization
+/// are notified by mail of the deletion.
 pub async fn delete_apikey(
     session: Session,
     request: HttpRequest,
@@ -137,24 +244,85 @@ pub async fn delete_apikey(
     info!("Route DELETE /api/key");
 
     let sptk = get_header_value(&request, "SPTK")?;
-    let user_uuid = get_user_session(&session)?;
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
+    let meta = RequestMeta::from_request(&request);
 
+    let dbc = db.clone();
     let res = web::block(move || {
-        let conn = &mut db.pool.get()?;
-        let sptk = sptk.to_str().unwrap();
+        let conn = &mut dbc.pool.get()?;
+        let sptk = sptk
+            .to_str()
+            .map_err(|_| ApiError::AuthorizationError(None))?;
 
-        // Check if the entry exists for that user
-        let exists = ApiKey::exists_by_owner_and_key(conn, &user_uuid, sptk)?;
+        OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::KeyDelete)?;
+        // Check if the entry exists for that organization
+        let api_key = match OrgApiKey::get_by_key_and_org(conn, &org_uuid, sptk)? {
+            Some(api_key) => api_key,
+            None => return Err(ApiError::AuthorizationError(None)),
+        };
 
-        if exists {
-            Ok(ApiKey::delete(conn, sptk)?)
-        } else {
-            Err(ApiError::AuthorizationError(None))
+        let mut recipients = OrgMember::get_owners(conn, &org_uuid)?;
+        if !recipients.contains(&api_key.customer_id) {
+            recipients.push(api_key.customer_id);
         }
+        // Keep the id of the key for the audit log, never the key itself
+        Ok((api_key.id, ApiKey::delete(conn, sptk)?, recipients))
     })
-    .await??;
+    .await?;
+
+    let mut event =
+        AuditEvent::new(AuditAction::ApiKeyDelete, user_uuid.to_string()).customer(user_uuid);
+    if let Ok((key_id, _, _)) = &res {
+        event = event.target(key_id.to_string());
+    }
+    event.record(&db, &meta, res.is_ok()).await;
+    let (key_id, res, recipients) = res?;
+
+    if res > 0 {
+        for recipient in recipients {
+            notify(&db, recipient, SecurityEvent::KeyDelete { key_id }, &meta).await;
+        }
+    }
 
     // Return the number of row affected (1 if went well, 0 otherwise)
     // TODO - May return Ok if 1 and Err if 0?
     Ok(HttpResponse::Ok().body(res.to_string()))
 }
+
+/// DELETE /api/key/host?id
+///
+/// Unbind the host from the ApiKey (id) of the active organization,
+/// allowing a new host to bind itself using PATCH /api/key.
+/// Require the KeyBind permission.
+pub async fn unbind_apikey(
+    session: Session,
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<SpecificKey>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route DELETE /api/key/host");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
+    let meta = RequestMeta::from_request(&request);
+    let key_id = info.id;
+
+    let dbc = db.clone();
+    let res = web::block(move || {
+        let conn = &mut dbc.pool.get()?;
+        OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::KeyBind)?;
+        let api_key = OrgApiKey::get_by_id_and_org(conn, &org_uuid, key_id)?;
+        OrgApiKey::set_host(conn, api_key.id, None)
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::ApiKeyUnbind, user_uuid.to_string())
+        .customer(user_uuid)
+        .target(key_id.to_string())
+        .record(&db, &meta, res.is_ok())
+        .await;
+    res?;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,26 @@
+use actix_session::Session;
+use actix_web::{web, HttpResponse};
+use sproot::{apierrors::ApiError, models::AuthPool};
+
+use super::Paged;
+use crate::{api::get_user_session, models::AuditLog};
+
+/// GET /api/audit?size&page
+///
+/// Page through the audit events of the currently logged user
+pub async fn get_audit_logs(
+    session: Session,
+    db: web::Data<AuthPool>,
+    info: web::Query<Paged>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/audit");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let (size, page) = info.get();
+
+    let data =
+        web::block(move || AuditLog::get_by_customer(&mut db.pool.get()?, &user_uuid, size, page))
+            .await??;
+
+    Ok(HttpResponse::Ok().json(data))
+}
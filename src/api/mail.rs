@@ -0,0 +1,89 @@
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sproot::{apierrors::ApiError, models::AuthPool};
+
+use crate::{
+    api::check_bounce_token,
+    models::Account,
+    utils::{
+        audit::{AuditAction, AuditEvent, RequestMeta},
+        bounces::{parse_json, parse_report, BounceEvent},
+        email::normalize_email,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BounceToken {
+    pub token: Option<String>,
+}
+
+/// POST /api/mail/bounce?token
+///
+/// Ingest the bounces and complaints reported by the mail provider, as
+/// the generic JSON (application/json) or as a delivery status notification
+/// (RFC 3464) / abuse report (RFC 5965). The customers owning the emails
+/// are marked accordingly, the hard bounces stopping their magic links.
+/// Return the number of reports matching a customer.
+pub async fn handle_bounce(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<BounceToken>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/mail/bounce");
+
+    check_bounce_token(&request, info.token.as_deref())?;
+    let meta = RequestMeta::from_request(&request);
+
+    let events = if request.content_type().ends_with("json") {
+        parse_json(&body)?
+    } else {
+        parse_report(&String::from_utf8_lossy(&body))
+    };
+    // Match the emails as stored, the invalid ones being skipped
+    let events: Vec<BounceEvent> = events
+        .into_iter()
+        .filter_map(|event| match normalize_email(&event.email) {
+            Ok(email) => Some(BounceEvent { email, ..event }),
+            Err(_) => {
+                trace!("BOUNCE: invalid recipient {}", event.email);
+                None
+            }
+        })
+        .collect();
+    if events.is_empty() {
+        return Err(ApiError::InvalidRequestError(None));
+    }
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<_, ApiError> {
+        let conn = &mut dbc.pool.get()?;
+        events
+            .into_iter()
+            .map(|event| {
+                let customer = Account::record_bounce(
+                    conn,
+                    &event.email,
+                    event.state,
+                    event.reason.as_deref(),
+                )?;
+                Ok((event, customer))
+            })
+            .collect::<Result<Vec<_>, ApiError>>()
+    })
+    .await??;
+
+    // The reports for unknown emails are recorded as failures
+    let mut matched = 0;
+    for (event, customer) in res {
+        let target = format!("{}:{}", event.state.as_str(), event.email);
+        let mut audit = AuditEvent::new(AuditAction::MailBounce, "mail-provider").target(target);
+        if let Some(customer_id) = customer {
+            audit = audit.customer(customer_id);
+            matched += 1;
+        }
+        audit.record(&db, &meta, customer.is_some()).await;
+    }
+
+    Ok(HttpResponse::Ok().body(matched.to_string()))
+}
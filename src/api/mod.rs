@@ -1,11 +1,31 @@
 use actix_session::Session;
-use actix_web::{http::header::HeaderValue, HttpRequest};
+use actix_web::{
+    http::header::{self, HeaderValue},
+    web, HttpRequest,
+};
+use base64::Engine;
 use lettre::message::Mailbox;
 use serde::{Deserialize, Serialize};
-use sproot::apierrors::ApiError;
+use sha2::{Digest, Sha256};
+use sproot::{apierrors::ApiError, models::AuthPool};
 use uuid::Uuid;
 
+use crate::{
+    models::{suspended_error, CustomerSession, CustomerSessionDTO},
+    utils::{
+        audit::RequestMeta,
+        email::normalize_email,
+        notifications::{notify, SecurityEvent},
+    },
+    CONFIG,
+};
+
+pub mod account;
+pub mod admin;
 pub mod apikey;
+pub mod audit;
+pub mod mail;
+pub mod org;
 pub mod sso;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,6 +48,22 @@ pub struct SpecificKey {
     pub id: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Paged {
+    pub size: Option<i64>,
+    pub page: Option<i64>,
+}
+
+impl Paged {
+    /// Return the (size, page) with size capped at 100
+    pub fn get(&self) -> (i64, i64) {
+        (
+            self.size.unwrap_or(100).clamp(1, 100),
+            self.page.unwrap_or(0).max(0),
+        )
+    }
+}
+
 /// Return the HeaderValue of the header 'header_name'
 /// or return an ApiError - InvalidRequest if not present
 pub fn get_header_value(req: &HttpRequest, header_name: &str) -> Result<HeaderValue, ApiError> {
@@ -37,37 +73,182 @@ pub fn get_header_value(req: &HttpRequest, header_name: &str) -> Result<HeaderVa
     }
 }
 
-/// Get the Uuid of the user from his Session or
-/// return an InvalidToken error if not found
-pub fn get_user_session(session: &Session) -> Result<Uuid, ApiError> {
-    match session.get::<String>("user_id") {
-        Ok(Some(id)) => Ok(Uuid::parse_str(&id).unwrap()),
-        _ => Err(ApiError::SessionError(None)),
+/// Get the Uuid of the user from his Session or return a SessionError
+/// if not found or if the session was revoked server side.
+/// A suspended customer gets the account_suspended error instead.
+pub async fn get_user_session(
+    session: &Session,
+    db: &web::Data<AuthPool>,
+) -> Result<Uuid, ApiError> {
+    let (user_uuid, session_uuid) = match (
+        session.get::<String>("user_id"),
+        session.get::<String>("session_id"),
+    ) {
+        (Ok(Some(uid)), Ok(Some(sid))) => match (Uuid::parse_str(&uid), Uuid::parse_str(&sid)) {
+            (Ok(uid), Ok(sid)) => (uid, sid),
+            _ => return Err(ApiError::SessionError(None)),
+        },
+        _ => return Err(ApiError::SessionError(None)),
+    };
+
+    let dbc = db.clone();
+    let suspended = web::block(move || {
+        CustomerSession::get_active_suspension(&mut dbc.pool.get()?, &session_uuid, &user_uuid)
+    })
+    .await??;
+
+    match suspended {
+        // The sessions are revoked on suspension, this only covers the
+        // requests racing with it.
+        Some(true) => {
+            session.purge();
+            Err(suspended_error())
+        }
+        Some(false) => Ok(user_uuid),
+        None => {
+            // Drop the cookie of the revoked session
+            session.purge();
+            Err(ApiError::SessionError(None))
+        }
+    }
+}
+
+/// Record a new server side session for the customer and store it in
+/// the cookie Session, which allow the session to be revoked later on.
+/// The customer is notified of the sessions opened from a new device.
+pub async fn open_session(
+    session: &Session,
+    db: &web::Data<AuthPool>,
+    customer_id: Uuid,
+    meta: &RequestMeta,
+) -> Result<(), ApiError> {
+    let dbc = db.clone();
+    let origin = meta.clone();
+    let (record, known) = web::block(move || -> Result<_, ApiError> {
+        let conn = &mut dbc.pool.get()?;
+        let known =
+            CustomerSession::is_known_device(conn, &customer_id, origin.user_agent.as_deref())?;
+        let record = CustomerSession::insert_and_get(
+            conn,
+            &CustomerSessionDTO {
+                customer_id,
+                ip: origin.ip.as_deref(),
+                user_agent: origin.user_agent.as_deref(),
+            },
+        )?;
+        Ok((record, known))
+    })
+    .await??;
+
+    session.renew();
+    session.insert("user_id", customer_id.to_string())?;
+    session.insert("session_id", record.id.to_string())?;
+
+    if !known {
+        notify(db, customer_id, SecurityEvent::NewLogin, meta).await;
+    }
+    Ok(())
+}
+
+/// Get the Uuid of the active organization from the Session,
+/// default to the personal organization of the user (same Uuid).
+///
+/// The membership of the user is not checked here and must be
+/// verified against the database before using the organization.
+pub fn get_active_org(session: &Session, user_uuid: &Uuid) -> Result<Uuid, ApiError> {
+    match session.get::<String>("org_id")? {
+        Some(id) => Ok(Uuid::parse_str(&id)?),
+        None => Ok(*user_uuid),
+    }
+}
+
+/// Authenticate the admin using the Basic Authorization header (name:token)
+/// against the admins defined in the config, and return the admin's name.
+/// The admin credentials are distinct from the customers' sessions.
+pub fn check_admin(req: &HttpRequest) -> Result<String, ApiError> {
+    let header = get_header_value(req, header::AUTHORIZATION.as_str())?;
+    let credentials = header
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| base64::prelude::BASE64_STANDARD.decode(encoded).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or(ApiError::AuthorizationError(None))?;
+    let (name, token) = credentials
+        .split_once(':')
+        .ok_or(ApiError::AuthorizationError(None))?;
+
+    match CONFIG.admins.iter().find(|admin| admin.name == name) {
+        Some(admin) if token_matches(token, &admin.token_sha256) => Ok(admin.name.to_owned()),
+        _ => Err(ApiError::AuthorizationError(None)),
+    }
+}
+
+/// Authenticate the mail provider reporting the bounces, using the
+/// Bearer Authorization header or the token query param (for the
+/// providers which can only be given an url). Disabled without token.
+pub fn check_bounce_token(req: &HttpRequest, query_token: Option<&str>) -> Result<(), ApiError> {
+    let expected = match &CONFIG.bounce_token_sha256 {
+        Some(expected) => expected,
+        None => return Err(ApiError::AuthorizationError(None)),
+    };
+    let header_token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match header_token.or(query_token) {
+        Some(token) if token_matches(token, expected) => Ok(()),
+        _ => Err(ApiError::AuthorizationError(None)),
     }
 }
 
+/// Compare the token with the sha256 (hex) of the expected one,
+/// as only the sha256 of the tokens are stored in the config.
+fn token_matches(token: &str, sha256: &str) -> bool {
+    let digest: String = Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    constant_time_eq(digest.as_bytes(), sha256.to_lowercase().as_bytes())
+}
+
+/// Compare two slices without leaking the position of the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 /// Simply return an error if the user is already logged.
 /// Used to protect the login route (sso)
-pub fn exit_if_logged(session: &Session) -> Result<(), ApiError> {
-    // Check if the user is already "logged" (don't override a user_id)
-    if (session.get::<String>("user_id")?).is_some() {
+pub async fn exit_if_logged(session: &Session, db: &web::Data<AuthPool>) -> Result<(), ApiError> {
+    // Check if the user is already "logged" (don't override a user_id),
+    // a revoked session doesn't count as logged.
+    if (session.get::<String>("user_id")?).is_some() && get_user_session(session, db).await.is_ok()
+    {
         Err(ApiError::InvalidRequestError(None))
     } else {
+        session.purge();
         Ok(())
     }
 }
 
-/// Return the plain text email and the Mailbox object
+/// Return the normalized email and the Mailbox object
 /// from the EmailSso or return an error if the email
-/// is not correctly formatted.
+/// is not correctly formatted (display names are refused).
 pub fn extract_mailbox(wemail: EmailSso) -> Result<(String, Mailbox), ApiError> {
-    // This act as a email verification (Regex is used)
-    let mailboxed: Mailbox = match wemail.email.parse() {
-        Ok(recv) => recv,
-        Err(_) => {
-            return Err(ApiError::InvalidRequestError(None));
-        }
-    };
+    let email = normalize_email(&wemail.email)?;
+    let mailboxed = Mailbox::new(
+        None,
+        email
+            .parse()
+            .map_err(|_| ApiError::InvalidRequestError(None))?,
+    );
 
-    Ok((wemail.email, mailboxed))
+    Ok((email, mailboxed))
 }
@@ -0,0 +1,535 @@
+use actix_session::Session;
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::Engine;
+use chrono::{Duration, NaiveDateTime};
+use diesel::Connection;
+use serde::{Deserialize, Serialize};
+use sproot::{apierrors::ApiError, models::AuthPool};
+use uuid::Uuid;
+
+use super::{EmailSso, JwtToken, Specific};
+use crate::{
+    api::{
+        extract_mailbox, get_active_org, get_user_session, open_session,
+        sso::{register_customer, registration_closed_error},
+    },
+    models::{
+        Account, OrgApiKey, OrgInvitation, OrgInvitationDTO, OrgMember, OrgRole, Organization,
+        OrganizationDTO, Permission,
+    },
+    utils::{
+        audit::{AuditAction, AuditEvent, RequestMeta},
+        config::RegistrationMode,
+        i18n::Locale,
+        jwt::{self, Purpose},
+        mail_invite::send_invite_mail,
+    },
+    CONFIG,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrgName {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberTarget {
+    pub uuid: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Invite {
+    pub email: String,
+    pub role: Option<OrgRole>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoleUpdate {
+    pub customer_id: Uuid,
+    pub role: OrgRole,
+}
+
+/// What the invitation page show before the invitee confirms joining
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitationPreview {
+    pub org_name: String,
+    pub inviter: String,
+    pub email: String,
+    pub role: String,
+    pub expires_at: NaiveDateTime,
+}
+
+/// Get the invitation id from the (base64 encoded) signed invitation token
+fn decode_invite_jwt(jwt: &str) -> Result<Uuid, ApiError> {
+    let decoded = base64::prelude::BASE64_STANDARD
+        .decode(jwt)
+        .map_err(|_| ApiError::AuthorizationError(None))?;
+    let invite_id = jwt::decode_purpose_jwt(
+        Purpose::Invite,
+        std::str::from_utf8(&decoded).map_err(|_| ApiError::AuthorizationError(None))?,
+    )?;
+
+    Ok(Uuid::parse_str(&invite_id)?)
+}
+
+/// POST /api/org
+///
+/// Create a new organization with the currently logged user as owner
+pub async fn post_org(
+    session: Session,
+    db: web::Data<AuthPool>,
+    info: web::Json<OrgName>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/org");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+
+    let name = info.name.trim().to_owned();
+    if name.is_empty() || name.len() > 255 {
+        return Err(ApiError::InvalidRequestError(None));
+    }
+
+    let data = web::block(move || {
+        Organization::create_with_member(
+            &mut db.pool.get()?,
+            &OrganizationDTO {
+                id: None,
+                name: &name,
+            },
+            &user_uuid,
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// GET /api/org/list
+///
+/// List the organizations the currently logged user is a member of
+pub async fn get_orgs(session: Session, db: web::Data<AuthPool>) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/org/list");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+
+    let data =
+        web::block(move || Organization::get_by_member(&mut db.pool.get()?, &user_uuid)).await??;
+
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// POST /api/org/select?uuid
+///
+/// Change the active organization of the Session,
+/// the key routes will operate on this organization.
+pub async fn select_org(
+    session: Session,
+    db: web::Data<AuthPool>,
+    info: web::Query<Specific>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/org/select");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = Uuid::parse_str(&info.uuid)?;
+
+    web::block(move || OrgMember::ensure(&mut db.pool.get()?, &org_uuid, &user_uuid)).await??;
+
+    session.insert("org_id", org_uuid.to_string())?;
+    Ok(HttpResponse::Ok().body(org_uuid.to_string()))
+}
+
+/// GET /api/org/members
+///
+/// List the members (and their role) of the active organization
+pub async fn get_members(
+    session: Session,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/org/members");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
+
+    let data = web::block(move || {
+        let conn = &mut db.pool.get()?;
+        OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::MemberList)?;
+        OrgMember::get_by_org(conn, &org_uuid)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// POST /api/org/invite
+///
+/// Invite someone, by email, to join the active organization with the
+/// given role (default to member). The invitation is sent by mail with a
+/// signed link valid for invite_validity_hours. Require the MemberInvite
+/// permission and the inviter must be allowed to manage the invited role.
+/// Only the existing customers can be invited when the registration is closed.
+pub async fn invite_member(
+    session: Session,
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Json<Invite>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/org/invite");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
+    let meta = RequestMeta::from_request(&request);
+    let Invite { email, role } = info.into_inner();
+    let role = role.unwrap_or(OrgRole::Member);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<String, ApiError> {
+        let (email, mailboxed) = extract_mailbox(EmailSso { email })?;
+        let conn = &mut dbc.pool.get()?;
+
+        let current = OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::MemberInvite)?;
+        if !current.can_manage(role) {
+            return Err(Permission::MemberManage.denied());
+        }
+        // The invitation of an unknown email couldn't be accepted
+        let invitee = Account::get_by_email(conn, &email)?;
+        if invitee.is_none() && CONFIG.registration_mode == RegistrationMode::Closed {
+            return Err(registration_closed_error());
+        }
+
+        let validity = Duration::hours(CONFIG.invite_validity_hours);
+        let org = Organization::get(conn, &org_uuid)?;
+        let inviter = Account::get(conn, &user_uuid)?;
+        let invitation = OrgInvitation::upsert(
+            conn,
+            &OrgInvitationDTO {
+                org_id: org_uuid,
+                email: &email,
+                invited_by: user_uuid,
+                role: role.as_str(),
+            },
+            CONFIG.invite_validity_hours,
+        )?;
+
+        // Create the signed invitation token, base64 encoded for convenience
+        let jwt = jwt::create_purpose_jwt(Purpose::Invite, &invitation.id.to_string(), validity)?;
+        let encoded = base64::prelude::BASE64_STANDARD.encode(jwt);
+        // In the invitee's locale if he has an account, the inviter's otherwise
+        let locale = match invitee {
+            Some(invitee) => invitee.locale(),
+            None => inviter.locale(),
+        };
+
+        send_invite_mail(
+            conn,
+            mailboxed,
+            locale,
+            &org.name,
+            &inviter.email,
+            role.as_str(),
+            &invitation.expires_at,
+            &encoded,
+        )?;
+
+        Ok(email)
+    })
+    .await?;
+
+    let mut event =
+        AuditEvent::new(AuditAction::OrgInvite, user_uuid.to_string()).customer(user_uuid);
+    if let Ok(email) = &res {
+        event = event.target(format!("{}:{}", org_uuid, email));
+    }
+    event.record(&db, &meta, res.is_ok()).await;
+    res?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// GET /api/org/invitations
+///
+/// List the pending invitations of the active organization.
+/// Require the MemberInvite permission.
+pub async fn get_invitations(
+    session: Session,
+    db: web::Data<AuthPool>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/org/invitations");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
+
+    let data = web::block(move || {
+        let conn = &mut db.pool.get()?;
+        OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::MemberInvite)?;
+        OrgInvitation::get_pending_by_org(conn, &org_uuid)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// DELETE /api/org/invite?uuid
+///
+/// Revoke a pending invitation of the active organization, the link
+/// sent by mail can no longer be used. Require the MemberInvite permission.
+pub async fn revoke_invitation(
+    session: Session,
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<Specific>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route DELETE /api/org/invite");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
+    let invite_uuid = Uuid::parse_str(&info.uuid)?;
+    let meta = RequestMeta::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || {
+        let conn = &mut dbc.pool.get()?;
+        OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::MemberInvite)?;
+        OrgInvitation::delete(conn, &org_uuid, &invite_uuid)
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::OrgInviteRevoke, user_uuid.to_string())
+        .customer(user_uuid)
+        .target(invite_uuid.to_string())
+        .record(&db, &meta, matches!(res, Ok(1)))
+        .await;
+
+    match res? {
+        0 => Err(ApiError::InvalidRequestError(None)),
+        _ => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+/// GET /api/org/join?jwt
+///
+/// Describe the invitation from the link sent by mail (organization,
+/// inviter, role) for the page asking the invitee to confirm, without
+/// accepting it: following the link alone must not join the organization.
+pub async fn get_invitation(
+    db: web::Data<AuthPool>,
+    jwt_holder: web::Query<JwtToken>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/org/join");
+
+    let data = web::block(move || -> Result<InvitationPreview, ApiError> {
+        let invite_id = decode_invite_jwt(&jwt_holder.jwt)?;
+        let conn = &mut db.pool.get()?;
+        // A revoked invitation no longer exists
+        let invitation = OrgInvitation::get_pending(conn, &invite_id)?
+            .ok_or(ApiError::AuthorizationError(None))?;
+
+        Ok(InvitationPreview {
+            org_name: Organization::get(conn, &invitation.org_id)?.name,
+            inviter: Account::get(conn, &invitation.invited_by)?.email,
+            email: invitation.email,
+            role: invitation.role,
+            expires_at: invitation.expires_at,
+        })
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// POST /api/org/join?jwt
+///
+/// Accept the invitation once confirmed on the page of the link sent by mail.
+/// As the link prove the ownership of the email, the customer is logged in,
+/// or registered first (as /api/rsso does) if no account exists for that
+/// email, and then join the organization with the invited role. The
+/// registration is refused when closed, the invitation being enough when
+/// it's invite-only.
+pub async fn join_org(
+    session: Session,
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    jwt_holder: web::Query<JwtToken>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/org/join");
+
+    let logged = get_user_session(&session, &db)
+        .await
+        .ok()
+        .map(|id| id.to_string());
+    let meta = RequestMeta::from_request(&request);
+    // Locale of the account if it's created by joining
+    let locale = Locale::from_request(&request);
+
+    let dbc = db.clone();
+    let is_logged = logged.is_some();
+    let res = web::block(move || -> Result<(Uuid, Uuid), ApiError> {
+        let invite_id = decode_invite_jwt(&jwt_holder.jwt)?;
+
+        let conn = &mut dbc.pool.get()?;
+        // A revoked invitation no longer exists
+        let invitation = OrgInvitation::get_pending(conn, &invite_id)?
+            .ok_or(ApiError::AuthorizationError(None))?;
+
+        conn.transaction(|conn| {
+            let customer_id = match Account::get_by_email(conn, &invitation.email)? {
+                Some(account) => {
+                    // Suspended customers are not allowed to log in, nor to join
+                    account.ensure_not_suspended()?;
+                    account.id
+                }
+                // No account can be created when the registration is closed, while in
+                // invite-only the invitation of a member stands for the invite code.
+                None if CONFIG.registration_mode == RegistrationMode::Closed => {
+                    return Err(registration_closed_error())
+                }
+                None => register_customer(conn, &invitation.email, locale)?.id,
+            };
+
+            // Don't override the session of another logged user
+            if matches!(&logged, Some(id) if *id != customer_id.to_string()) {
+                return Err(ApiError::InvalidRequestError(None));
+            }
+
+            invitation.accept(conn, &customer_id)?;
+            Ok((customer_id, invitation.org_id))
+        })
+    })
+    .await?;
+
+    let (customer_id, org_uuid) = match res {
+        Ok(ids) => ids,
+        Err(err) => {
+            AuditEvent::new(AuditAction::OrgJoin, "anonymous")
+                .record(&db, &meta, false)
+                .await;
+            return Err(err);
+        }
+    };
+
+    AuditEvent::new(AuditAction::OrgJoin, customer_id.to_string())
+        .customer(customer_id)
+        .target(org_uuid.to_string())
+        .record(&db, &meta, true)
+        .await;
+
+    if !is_logged {
+        open_session(&session, &db, customer_id, &meta).await?;
+    }
+    session.insert("org_id", org_uuid.to_string())?;
+    Ok(HttpResponse::Ok().body(org_uuid.to_string()))
+}
+
+/// DELETE /api/org/member?uuid
+///
+/// Remove the member (uuid) from the active organization, which require the
+/// MemberManage permission, or leave it if no uuid is given. The personal
+/// organization of a user cannot be left and its last owner cannot be removed.
+pub async fn remove_member(
+    session: Session,
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<MemberTarget>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route DELETE /api/org/member");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
+    let target_uuid = info.uuid.unwrap_or(user_uuid);
+    let meta = RequestMeta::from_request(&request);
+
+    if org_uuid == target_uuid {
+        return Err(ApiError::InvalidRequestError(None));
+    }
+
+    let dbc = db.clone();
+    let res = web::block(move || {
+        let conn = &mut dbc.pool.get()?;
+        conn.transaction(|conn| {
+            let target_role = match OrgMember::get_role(conn, &org_uuid, &target_uuid)? {
+                Some(role) => role,
+                None => return Err(ApiError::InvalidRequestError(None)),
+            };
+            if target_uuid != user_uuid {
+                let role =
+                    OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::MemberManage)?;
+                if !role.can_manage(target_role) {
+                    return Err(Permission::MemberManage.denied());
+                }
+            }
+            if target_role == OrgRole::Owner && OrgMember::lock_owners(conn, &org_uuid)? <= 1 {
+                return Err(ApiError::InvalidRequestError(None));
+            }
+
+            // The keys he created stay with the organization
+            OrgApiKey::hand_over(conn, &target_uuid, &org_uuid)?;
+            OrgMember::delete(conn, &org_uuid, &target_uuid)
+        })
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::OrgLeave, user_uuid.to_string())
+        .customer(target_uuid)
+        .target(org_uuid.to_string())
+        .record(&db, &meta, res.is_ok())
+        .await;
+    res?;
+
+    // Fallback to the personal organization
+    if target_uuid == user_uuid {
+        session.remove("org_id");
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// POST /api/org/role
+///
+/// Change the role of a member of the active organization.
+/// Require the MemberManage permission, and only the owners
+/// can manage the other owners. The last owner cannot be demoted.
+pub async fn update_role(
+    session: Session,
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Json<RoleUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route POST /api/org/role");
+
+    let user_uuid = get_user_session(&session, &db).await?;
+    let org_uuid = get_active_org(&session, &user_uuid)?;
+    let meta = RequestMeta::from_request(&request);
+    let RoleUpdate { customer_id, role } = info.into_inner();
+
+    let dbc = db.clone();
+    let res = web::block(move || {
+        let conn = &mut dbc.pool.get()?;
+        conn.transaction(|conn| {
+            let current =
+                OrgMember::authorize(conn, &org_uuid, &user_uuid, Permission::MemberManage)?;
+            let target_role = match OrgMember::get_role(conn, &org_uuid, &customer_id)? {
+                Some(role) => role,
+                None => return Err(ApiError::InvalidRequestError(None)),
+            };
+            if !current.can_manage(target_role) || !current.can_manage(role) {
+                return Err(Permission::MemberManage.denied());
+            }
+            if target_role == OrgRole::Owner
+                && role != OrgRole::Owner
+                && OrgMember::lock_owners(conn, &org_uuid)? <= 1
+            {
+                return Err(ApiError::InvalidRequestError(None));
+            }
+
+            OrgMember::update_role(conn, &org_uuid, &customer_id, role)
+        })
+    })
+    .await?;
+
+    AuditEvent::new(AuditAction::OrgRoleChange, user_uuid.to_string())
+        .customer(customer_id)
+        .target(format!("{}:{}", org_uuid, role.as_str()))
+        .record(&db, &meta, res.is_ok())
+        .await;
+    res?;
+
+    Ok(HttpResponse::Ok().finish())
+}
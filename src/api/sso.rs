@@ -1,6 +1,10 @@
 use actix_session::Session;
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use base64::Engine;
+use chrono::Duration;
+use diesel::Connection;
+use lettre::message::Mailbox;
+use serde::{Deserialize, Serialize};
 use sproot::{
     apierrors::ApiError,
     models::{AuthPool, Customers, CustomersDTO, DtoBase},
@@ -8,70 +12,324 @@ use sproot::{
 use uuid::Uuid;
 
 use crate::{
-    api::{exit_if_logged, extract_mailbox, EmailSso, JwtToken},
-    utils::{jwt, mail_sso::send_sso_mail},
+    api::{exit_if_logged, extract_mailbox, get_user_session, open_session, EmailSso, JwtToken},
+    models::{
+        email_taken_error, undeliverable_error, Account, CustomerSession, InviteCode, Organization,
+        OrganizationDTO, RateCounter,
+    },
+    utils::{
+        audit::{AuditAction, AuditEvent, RequestMeta},
+        config::RegistrationMode,
+        domain_policy::check_signup_domain,
+        email::{email_domain, normalize_email},
+        i18n::Locale,
+        jwt::{self, Purpose},
+        mail_sso::{send_register_mail, send_sso_mail, REGISTER_LINK_MINUTES},
+        pow::{self, check_pow, Challenge},
+        rate_limit::{check_limits, RateLimit},
+    },
+    ConnType, CONFIG,
 };
 
 /// POST /api/sso
 ///
-/// Login a customer (get a Magic Link Mail)
+/// Sign in (or up) a customer: get a Magic Link Mail if the account
+/// exists, otherwise a link to create it. The response (and its timing)
+/// never disclose whether the account exists as the lookup and the
+/// mail are done in the background, the domain policy being checked
+/// before for every email so that its error code can be shown.
 pub async fn handle_sso(
+    request: HttpRequest,
     db: web::Data<AuthPool>,
     session: Session,
     wemail: web::Json<EmailSso>,
 ) -> Result<HttpResponse, ApiError> {
     info!("Route POST /api/sso");
 
-    exit_if_logged(&session)?;
-
-    web::block(move || {
-        let (email, mailboxed) = extract_mailbox(wemail.into_inner())?;
-        // Get the customer_id from the email
-        let customer = Customers::get_specific(&mut db.pool.get()?, &email)?;
-        // Create the JWT token
-        let jwt = jwt::create_jwt(&customer.id.to_string())?;
-        // Encode it in base64 for convenience
-        let encoded = base64::prelude::BASE64_STANDARD.encode(jwt);
-        // Send the mail for the JWT token
-        // at first, send it immediately, but then we can consider
-        // creating a Queue for the mails to be sent (avoid limit, ...)
-        send_sso_mail(mailboxed, &encoded)
+    exit_if_logged(&session, &db).await?;
+
+    let meta = RequestMeta::from_request(&request);
+    // Only the format and the domain of the email are checked before answering
+    let (email, mailboxed) = extract_mailbox(wemail.into_inner())?;
+    check_signup_domain(email_domain(&email))?;
+    check_pow(&request, &db, Some(&email)).await?;
+
+    // Limit the mails sent from a same ip, and to a same email from that ip.
+    // The limit of the email alone is higher, so that exhausting it (to stop
+    // the customer from getting his links) takes many ips, while still
+    // bounding the mails a victim gets from rotating ips.
+    let mut limits = sso_ip_limit(&meta);
+    limits.push(RateLimit::new(
+        "sso",
+        "email-ip",
+        format!("{}|{}", email, meta.ip.as_deref().unwrap_or_default()),
+        CONFIG.rate_limits.sso_per_email,
+    ));
+    limits.push(RateLimit::new(
+        "sso",
+        "email",
+        &email,
+        CONFIG.rate_limits.sso_per_recipient,
+    ));
+    if let Some(limited) = check_limits(&db, limits).await? {
+        return Ok(limited.response());
+    }
+
+    // The locale of the sign up mail, as there's no account (yet)
+    let locale = Locale::from_request(&request);
+    actix_web::rt::spawn(send_sign_in_mail(db, meta, email, mailboxed, locale));
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChallengeFor {
+    pub email: Option<String>,
+}
+
+/// GET /api/challenge?email
+///
+/// Get a proof of work challenge to solve before calling POST /api/sso,
+/// its difficulty depends on the number of challenges recently requested
+/// from the same ip or for the same email, to which the challenge is bound.
+pub async fn handle_challenge(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    info: web::Query<ChallengeFor>,
+) -> Result<HttpResponse, ApiError> {
+    info!("Route GET /api/challenge");
+
+    let meta = RequestMeta::from_request(&request);
+    let email = match &info.email {
+        Some(email) => normalize_email(email)?,
+        None => String::new(),
+    };
+
+    let data = web::block(move || -> Result<Challenge, ApiError> {
+        let conn = &mut db.pool.get()?;
+        let window = CONFIG.pow.window_secs;
+        let mut volume = 0;
+        if let Some(ip) = &meta.ip {
+            volume = RateCounter::hit(conn, &format!("pow:ip:{}", ip), window)?.count;
+        }
+        if !email.is_empty() {
+            let count = RateCounter::hit(conn, &format!("pow:email:{}", email), window)?.count;
+            volume = volume.max(count);
+        }
+
+        pow::issue(pow::difficulty_for(volume), &email)
     })
     .await??;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(HttpResponse::Ok().json(data))
 }
 
-/// POST /api/rsso
+/// Limit of the requests to the sso routes from the ip of the request
+fn sso_ip_limit(meta: &RequestMeta) -> Vec<RateLimit> {
+    meta.ip
+        .iter()
+        .map(|ip| RateLimit::new("sso", "ip", ip, CONFIG.rate_limits.sso_per_ip))
+        .collect()
+}
+
+/// Send the Magic Link to the customer or, if there's no account for
+/// the email, the link to create it. Suspended customers get nothing,
+/// nor do the unknown emails when the registration is closed and the
+/// hard bounced emails until bounce_retry_hours passed.
+async fn send_sign_in_mail(
+    db: web::Data<AuthPool>,
+    meta: RequestMeta,
+    email: String,
+    mailboxed: Mailbox,
+    locale: Option<Locale>,
+) {
+    let actor = email.to_owned();
+    let dbc = db.clone();
+    let res = match web::block(move || -> Result<Option<Uuid>, ApiError> {
+        let conn = &mut dbc.pool.get()?;
+        match Account::get_by_email(conn, &email)? {
+            Some(customer) => {
+                // Suspended customers are not allowed to log in
+                customer.ensure_not_suspended()?;
+                // Don't send to an email which hard bounced, unless it's time to retry
+                if customer.is_undeliverable() && !Account::retry_delivery(conn, &customer.id)? {
+                    return Err(undeliverable_error());
+                }
+                // Create the JWT token, encoded in base64 for convenience
+                let jwt = jwt::create_jwt(&customer.id.to_string())?;
+                let encoded = base64::prelude::BASE64_STANDARD.encode(jwt);
+                send_sso_mail(conn, mailboxed, customer.locale(), &encoded)?;
+                Ok(Some(customer.id))
+            }
+            None => {
+                if CONFIG.registration_mode == RegistrationMode::Closed {
+                    return Err(registration_closed_error());
+                }
+                // The token carry the (verified) email the account will be created for
+                let jwt = jwt::create_purpose_jwt(
+                    Purpose::Register,
+                    &email,
+                    Duration::minutes(REGISTER_LINK_MINUTES),
+                )?;
+                let encoded = base64::prelude::BASE64_STANDARD.encode(jwt);
+                send_register_mail(
+                    conn,
+                    mailboxed,
+                    locale.unwrap_or_else(|| Locale::resolve(None)),
+                    &encoded,
+                )?;
+                Ok(None)
+            }
+        }
+    })
+    .await
+    {
+        Ok(res) => res,
+        Err(err) => Err(err.into()),
+    };
+
+    let mut event = AuditEvent::new(AuditAction::SsoRequest, actor);
+    if let Ok(Some(customer_id)) = res {
+        event = event.customer(customer_id);
+    }
+    event.record(&db, &meta, res.is_ok()).await;
+
+    if let Err(err) = res {
+        trace!("SSO: no sign in mail sent: {}", err);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Registration {
+    pub jwt: String,
+    pub code: Option<String>,
+}
+
+/// POST /api/rsso?jwt&code
 ///
-/// Create a new customer based on his email address
-/// and then get a Magic Link Mail
+/// Create the customer using the token of the link sent by
+/// handle_sso to an email without account, and sign him in.
+/// The invite code is required if the registration is invite-only.
 pub async fn handle_rsso(
+    request: HttpRequest,
     db: web::Data<AuthPool>,
     session: Session,
-    wemail: web::Json<EmailSso>,
+    info: web::Query<Registration>,
 ) -> Result<HttpResponse, ApiError> {
     info!("Route POST /api/rsso");
 
-    exit_if_logged(&session)?;
-
-    web::block(move || {
-        let (email, mailboxed) = extract_mailbox(wemail.into_inner())?;
-        // Create the user and generate a customer_id (auto in Postgres)
-        let customer =
-            Customers::insert_and_get(&mut db.pool.get()?, &CustomersDTO { email: &email })?;
-        // Create the JWT token
-        let jwt = jwt::create_jwt(&customer.id.to_string())?;
-        // Encode it in base64 for convenience
-        let encoded = base64::prelude::BASE64_STANDARD.encode(jwt);
-        // Send the mail for the JWT token
-        // at first, send it immediately, but then we can consider
-        // creating a Queue for the mails to be sent (avoid limit, ...)
-        send_sso_mail(mailboxed, &encoded)
+    exit_if_logged(&session, &db).await?;
+
+    let meta = RequestMeta::from_request(&request);
+    if let Some(limited) = check_limits(&db, sso_ip_limit(&meta)).await? {
+        return Ok(limited.response());
+    }
+    let locale = Locale::from_request(&request);
+
+    let dbc = db.clone();
+    let res = web::block(move || -> Result<(Uuid, Option<String>), ApiError> {
+        let info = info.into_inner();
+        // Get the email from the jwt token
+        let email = match base64::prelude::BASE64_STANDARD.decode(&info.jwt) {
+            Ok(decoded) => jwt::decode_purpose_jwt(
+                Purpose::Register,
+                std::str::from_utf8(&decoded).map_err(|_| ApiError::AuthorizationError(None))?,
+            )?,
+            Err(_) => return Err(ApiError::AuthorizationError(None)),
+        };
+
+        let conn = &mut dbc.pool.get()?;
+        // The account may have been created since the link was sent
+        match Account::get_by_email(conn, &email)? {
+            Some(account) => {
+                account.ensure_not_suspended()?;
+                Ok((account.id, None))
+            }
+            // The code is only consumed if the customer is created
+            None => conn.transaction(|conn| {
+                let redeemed = check_registration(conn, info.code)?;
+                Ok((register_customer(conn, &email, locale)?.id, redeemed))
+            }),
+        }
     })
-    .await??;
+    .await?;
 
-    Ok(HttpResponse::Ok().finish())
+    let (customer_id, redeemed) = match res {
+        Ok(registered) => registered,
+        Err(err) => {
+            AuditEvent::new(AuditAction::SsoRegister, "anonymous")
+                .record(&db, &meta, false)
+                .await;
+            return Err(err);
+        }
+    };
+
+    let mut event =
+        AuditEvent::new(AuditAction::SsoRegister, customer_id.to_string()).customer(customer_id);
+    if let Some(code) = redeemed {
+        event = event.target(code);
+    }
+    event.record(&db, &meta, true).await;
+
+    open_session(&session, &db, customer_id, &meta).await?;
+    Ok(HttpResponse::Ok().body(customer_id.to_string()))
+}
+
+/// Error returned when no account can be created through the sso routes
+/// (or by accepting an invitation to an organization)
+pub fn registration_closed_error() -> ApiError {
+    ApiError::InvalidRequestError(Some("registration_closed".to_owned()))
+}
+
+/// Check that the registration mode allows to create an account,
+/// redeeming the invite code if required (and returning it).
+fn check_registration(
+    conn: &mut ConnType,
+    code: Option<String>,
+) -> Result<Option<String>, ApiError> {
+    match CONFIG.registration_mode {
+        RegistrationMode::Open => Ok(None),
+        RegistrationMode::Closed => Err(registration_closed_error()),
+        RegistrationMode::InviteOnly => match code {
+            Some(code) => {
+                InviteCode::redeem(conn, &code)?;
+                Ok(Some(code))
+            }
+            None => Err(ApiError::InvalidRequestError(Some(
+                "invite_code_required".to_owned(),
+            ))),
+        },
+    }
+}
+
+/// Create the customer along with his personal organization (sharing the same id),
+/// the locale (from the Accept-Language at sign up) being his preferred one.
+pub fn register_customer(
+    conn: &mut ConnType,
+    email: &str,
+    locale: Option<Locale>,
+) -> Result<Customers, ApiError> {
+    conn.transaction(|conn| {
+        // The emails are unique regardless of their case
+        if Account::get_by_email(conn, email)?.is_some() {
+            return Err(email_taken_error());
+        }
+        check_signup_domain(email_domain(email))?;
+        let customer = Customers::insert_and_get(conn, &CustomersDTO { email })?;
+        if let Some(locale) = locale {
+            Account::set_locale(conn, &customer.id, Some(locale.as_str()))?;
+        }
+        Organization::create_with_member(
+            conn,
+            &OrganizationDTO {
+                id: Some(customer.id),
+                name: email,
+            },
+            &customer.id,
+        )?;
+
+        Ok(customer)
+    })
 }
 
 /// GET /api/csso
@@ -79,52 +337,105 @@ pub async fn handle_rsso(
 /// Exchange the code from the callback for a CookieSession
 /// eg: http://xyz/api/csso?jwt=base64_jwttoken
 pub async fn handle_csso(
+    request: HttpRequest,
     db: web::Data<AuthPool>,
     session: Session,
     jwt_holder: web::Query<JwtToken>,
 ) -> Result<HttpResponse, ApiError> {
     info!("Route GET /api/csso");
 
-    exit_if_logged(&session)?;
+    exit_if_logged(&session, &db).await?;
 
-    let customer_id = web::block(move || {
+    let meta = RequestMeta::from_request(&request);
+    if let Some(limited) = check_limits(&db, sso_ip_limit(&meta)).await? {
+        return Ok(limited.response());
+    }
+
+    let dbc = db.clone();
+    let res = web::block(move || {
         // Get the customer_id from the jwt token
         let customer_id = match base64::prelude::BASE64_STANDARD.decode(&jwt_holder.jwt) {
-            Ok(decoded) => jwt::decode_jwt(std::str::from_utf8(&decoded).unwrap())?,
+            Ok(decoded) => jwt::decode_jwt(
+                std::str::from_utf8(&decoded).map_err(|_| ApiError::AuthorizationError(None))?,
+            )?,
             Err(_) => return Err(ApiError::AuthorizationError(None)),
         };
 
-        // Check if the customer_id exists in the database
-        if !Customers::exists(&mut db.pool.get()?, &Uuid::parse_str(&customer_id)?)? {
-            return Err(ApiError::AuthorizationError(None));
+        // Check if the customer_id exists in the database (and is not suspended)
+        let customer_uuid = Uuid::parse_str(&customer_id)?;
+        match Account::find(&mut dbc.pool.get()?, &customer_uuid)? {
+            Some(account) => account.ensure_not_suspended()?,
+            None => return Err(ApiError::AuthorizationError(None)),
         }
 
-        Ok(customer_id)
+        Ok(customer_uuid)
     })
-    .await??;
+    .await?;
+
+    let customer_id = match res {
+        Ok(customer_id) => customer_id,
+        Err(err) => {
+            AuditEvent::new(AuditAction::SsoLogin, "anonymous")
+                .record(&db, &meta, false)
+                .await;
+            return Err(err);
+        }
+    };
+
+    AuditEvent::new(AuditAction::SsoLogin, customer_id.to_string())
+        .customer(customer_id)
+        .record(&db, &meta, true)
+        .await;
 
     // If everything is correct, return a Cookie with the user_id == customer_id
-    session.insert("user_id", customer_id.clone())?;
-    Ok(HttpResponse::Ok().body(customer_id))
+    open_session(&session, &db, customer_id, &meta).await?;
+    Ok(HttpResponse::Ok().body(customer_id.to_string()))
 }
 
 /// Simple route that check if the user is logged
-pub async fn handle_who(session: Session) -> Result<HttpResponse, ApiError> {
+pub async fn handle_who(
+    db: web::Data<AuthPool>,
+    session: Session,
+) -> Result<HttpResponse, ApiError> {
     info!("Route GET /api/whoami");
 
-    // If there's no user_id in the session, it's not logged
-    if session.get::<String>("user_id")?.is_none() {
-        return Err(ApiError::AuthorizationError(None));
-    }
+    // If there's no (valid) session, it's not logged
+    let user_uuid = match get_user_session(&session, &db).await {
+        Ok(user_uuid) => user_uuid,
+        Err(_) => return Err(ApiError::AuthorizationError(None)),
+    };
 
-    Ok(HttpResponse::Ok().body(session.get::<String>("user_id")?.unwrap()))
+    Ok(HttpResponse::Ok().body(user_uuid.to_string()))
 }
 
 /// Clear the Session on client & server side
-pub async fn handle_logout(session: Session) -> Result<HttpResponse, ApiError> {
+pub async fn handle_logout(
+    request: HttpRequest,
+    db: web::Data<AuthPool>,
+    session: Session,
+) -> Result<HttpResponse, ApiError> {
     info!("Route GET /api/logout");
 
+    let meta = RequestMeta::from_request(&request);
+    let user = session.get::<String>("user_id")?;
+    let session_id = session.get::<String>("session_id")?;
+
     session.purge();
 
+    // Revoke the server side session so that the cookie can't be reused
+    if let Some(Ok(session_uuid)) = session_id.map(|sid| Uuid::parse_str(&sid)) {
+        let dbc = db.clone();
+        web::block(move || CustomerSession::revoke(&mut dbc.pool.get()?, &session_uuid)).await??;
+    }
+
+    // Only record the logout of sessions that were actually logged in
+    if let Some(user_id) = user {
+        let mut event = AuditEvent::new(AuditAction::Logout, user_id.to_owned());
+        if let Ok(customer_id) = Uuid::parse_str(&user_id) {
+            event = event.customer(customer_id);
+        }
+        event.record(&db, &meta, true).await;
+    }
+
     Ok(HttpResponse::Ok().finish())
 }
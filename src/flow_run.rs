@@ -1,11 +1,24 @@
 use diesel::{prelude::PgConnection, r2d2::ConnectionManager};
 use diesel_migrations::MigrationHarness;
 
-use crate::{server, utils::mail_sso::test_smtp_transport, Pool, CONFIG, MIGRATIONS};
+use crate::{
+    jobs,
+    models::Account,
+    server,
+    utils::{
+        jwt::test_keys,
+        mailer::{test_dkim, test_mail_transport},
+    },
+    Pool, CONFIG, MIGRATIONS,
+};
 
 fn build_pool(db_url: &str, max_conn: u32) -> Pool {
-    // Check if the SMTP server host is "ok"
-    test_smtp_transport();
+    // Check if the tokens can be signed and verified with the keys
+    test_keys();
+    // Check if the mails can be delivered (the SMTP server is "ok")
+    test_mail_transport();
+    // Check if the dkim key (if any) is usable
+    test_dkim();
 
     // Init the connection to the postgresql
     let manager = ConnectionManager::<PgConnection>::new(db_url);
@@ -45,6 +58,16 @@ fn apply_migration(pool: &Pool) {
         error!("Cannot apply the migrations: {}", e);
         std::process::exit(1);
     }
+
+    // Bring the stored emails to their normalized form, which the lookups use
+    match Account::normalize_stored_emails(pooled_conn) {
+        Ok(0) => {}
+        Ok(count) => info!("{} customers emails normalized", count),
+        Err(e) => {
+            error!("Cannot normalize the customers emails: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
 /// Will start the program normally
@@ -55,6 +78,9 @@ pub async fn flow_run_start() -> std::io::Result<()> {
     // Apply the migrations to the database
     apply_migration(&pool);
 
+    // Start the background jobs (deletion of the accounts, ...)
+    jobs::start_jobs(&pool);
+
     // Continue the initialization of the actix web server
     server::server(pool).await
 }
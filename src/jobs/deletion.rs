@@ -0,0 +1,83 @@
+use actix_web::{rt::time, web};
+use lettre::message::Mailbox;
+use sproot::{apierrors::ApiError, models::AuthPool};
+
+use crate::{
+    models::Account,
+    utils::{
+        audit::{AuditAction, AuditEvent, RequestMeta},
+        mail_deletion::send_deleted_mail,
+    },
+    CONFIG,
+};
+
+// How often the accounts pending deletion are checked
+const DELETION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Permanently delete the accounts whose grace period is over
+pub async fn deletion_job(db: web::Data<AuthPool>) {
+    let mut interval = time::interval(DELETION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let dbc = db.clone();
+        let expired = web::block(move || -> Result<Vec<Account>, ApiError> {
+            Account::get_expired_deletions(&mut dbc.pool.get()?, CONFIG.deletion_grace_days)
+        })
+        .await;
+
+        match expired {
+            Ok(Ok(accounts)) => {
+                for account in accounts {
+                    delete_account(&db, account).await;
+                }
+            }
+            Ok(Err(err)) => error!("DELETION: cannot get the accounts to delete: {}", err),
+            Err(err) => error!("DELETION: cannot get the accounts to delete: {}", err),
+        }
+    }
+}
+
+/// Delete the account and all its data, then send the final mail
+async fn delete_account(db: &web::Data<AuthPool>, account: Account) {
+    let customer_id = account.id;
+
+    let dbc = db.clone();
+    let res =
+        match web::block(move || Account::delete_with_data(&mut dbc.pool.get()?, &customer_id))
+            .await
+        {
+            Ok(res) => res,
+            Err(err) => Err(err.into()),
+        };
+
+    AuditEvent::new(AuditAction::AccountDelete, "system")
+        .customer(customer_id)
+        .record(db, &RequestMeta::default(), res.is_ok())
+        .await;
+
+    if let Err(err) = res {
+        error!(
+            "DELETION: cannot delete the account {}: {}",
+            customer_id, err
+        );
+        return;
+    }
+    info!("DELETION: account {} deleted", customer_id);
+
+    let locale = account.locale();
+    let mailboxed: Mailbox = match account.email.parse() {
+        Ok(mailboxed) => mailboxed,
+        Err(err) => {
+            error!("DELETION: invalid email for {}: {}", customer_id, err);
+            return;
+        }
+    };
+    let dbc = db.clone();
+    match web::block(move || send_deleted_mail(&mut dbc.pool.get()?, mailboxed, locale)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(err)) => error!("DELETION: cannot send the mail to {}: {}", customer_id, err),
+        Err(err) => error!("DELETION: cannot send the mail to {}: {}", customer_id, err),
+    }
+}
@@ -0,0 +1,103 @@
+use actix_web::{rt::time, web};
+use lettre::message::Mailbox;
+use sproot::{apierrors::ApiError, models::AuthPool};
+
+use crate::{
+    models::{Account, CustomerExport, DataExport},
+    utils::mail_export::send_export_mail,
+};
+
+// How often the pending exports are looked for
+const EXPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+// How often the expired exports are removed
+const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+// Time a claimed export is reserved for the worker generating it,
+// after which it's generated again (eg: after a restart)
+const EXPORT_LEASE_SECS: i32 = 600;
+
+/// Generate the pending exports, one at a time, and mail their link
+pub async fn export_job(db: web::Data<AuthPool>) {
+    let mut interval = time::interval(EXPORT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        loop {
+            let dbc = db.clone();
+            match web::block(move || generate_next(&dbc)).await {
+                Ok(Ok(true)) => {}
+                Ok(Ok(false)) => break,
+                Ok(Err(err)) => {
+                    error!("EXPORT: cannot generate the exports: {}", err);
+                    break;
+                }
+                Err(err) => {
+                    error!("EXPORT: cannot generate the exports: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Generate the next pending export, if any, and mail the customer the
+/// link. Return whether an export was processed, successfully or not.
+fn generate_next(db: &AuthPool) -> Result<bool, ApiError> {
+    let conn = &mut db.pool.get()?;
+    let export = match DataExport::claim_next(conn, EXPORT_LEASE_SECS)? {
+        Some(export) => export,
+        None => return Ok(false),
+    };
+
+    let archive = CustomerExport::collect(conn, &export.customer_id)
+        .and_then(|collected| collected.to_json())
+        .map(String::into_bytes);
+    if let Err(err) = &archive {
+        error!("EXPORT: cannot generate the export {}: {}", export.id, err);
+    }
+    let ready = archive.is_ok();
+    DataExport::complete(conn, &export.id, archive.ok())?;
+    if !ready {
+        return Ok(true);
+    }
+    info!("EXPORT: export {} ready", export.id);
+
+    // The export is ready even if the mail can't be sent,
+    // its id having been returned when it was requested.
+    let res = Account::get(conn, &export.customer_id).and_then(|account| {
+        let mailboxed: Mailbox = account
+            .email
+            .parse()
+            .map_err(|_| ApiError::ServerError(None))?;
+        send_export_mail(
+            conn,
+            mailboxed,
+            account.locale(),
+            &export.id,
+            &export.expires_at,
+        )
+    });
+    if let Err(err) = res {
+        error!("EXPORT: cannot send the mail of {}: {}", export.id, err);
+    }
+
+    Ok(true)
+}
+
+/// Remove the expired exports (and their archive)
+pub async fn cleanup_job(db: web::Data<AuthPool>) {
+    let mut interval = time::interval(CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let dbc = db.clone();
+        match web::block(move || DataExport::delete_expired(&mut dbc.pool.get()?)).await {
+            Ok(Ok(count)) => trace!("EXPORT: {} expired exports removed", count),
+            Ok(Err(err)) => error!("EXPORT: cannot remove the expired exports: {}", err),
+            Err(err) => error!("EXPORT: cannot remove the expired exports: {}", err),
+        }
+    }
+}
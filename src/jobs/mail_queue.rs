@@ -0,0 +1,80 @@
+use actix_web::{rt::time, web};
+use sproot::{apierrors::ApiError, models::AuthPool};
+
+use crate::{models::QueuedMail, utils::mailer::deliver, CONFIG};
+
+// How often the sent mails past their retention are removed
+const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+// Time a claimed mail is reserved for the worker delivering it
+const DELIVERY_LEASE_SECS: i32 = 300;
+
+/// Deliver the queued mails as they become due
+pub async fn delivery_job(db: web::Data<AuthPool>) {
+    let mut interval = time::interval(std::time::Duration::from_secs(
+        CONFIG.mail_queue.poll_secs.max(1),
+    ));
+
+    loop {
+        interval.tick().await;
+
+        let dbc = db.clone();
+        match web::block(move || deliver_due(&dbc)).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(count)) => trace!("MAIL: {} mails processed", count),
+            Ok(Err(err)) => error!("MAIL: cannot process the queue: {}", err),
+            Err(err) => error!("MAIL: cannot process the queue: {}", err),
+        }
+    }
+}
+
+/// Try to send a batch of due mails, returning how many were processed.
+/// The mails whose link expired are dead-lettered instead of being sent.
+fn deliver_due(db: &AuthPool) -> Result<usize, ApiError> {
+    let conn = &mut db.pool.get()?;
+    let expired = QueuedMail::expire_due(conn)?;
+    if expired > 0 {
+        warn!("MAIL: {} mails expired before their delivery", expired);
+    }
+    let mails = QueuedMail::claim_due(conn, CONFIG.mail_queue.batch_size, DELIVERY_LEASE_SECS)?;
+
+    // A mail failing to be updated is only logged: the others are still to be
+    // handled, and the lease prevents sending it again before DELIVERY_LEASE_SECS.
+    for mail in &mails {
+        let res = match deliver(mail) {
+            Ok(_) => QueuedMail::mark_sent(conn, &mail.id),
+            Err(err) => {
+                warn!(
+                    "MAIL: delivery of {} failed (attempt {}): {}",
+                    mail.id, mail.attempts, err
+                );
+                mail.mark_failed(conn, &err)
+            }
+        };
+        if let Err(err) = res {
+            error!("MAIL: cannot update the state of {}: {}", mail.id, err);
+        }
+    }
+
+    Ok(mails.len())
+}
+
+/// Remove the mails sent (or dead-lettered) more than retention_days ago
+pub async fn cleanup_job(db: web::Data<AuthPool>) {
+    let mut interval = time::interval(CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let dbc = db.clone();
+        match web::block(move || {
+            QueuedMail::delete_done(&mut dbc.pool.get()?, CONFIG.mail_queue.retention_days)
+        })
+        .await
+        {
+            Ok(Ok(count)) => trace!("MAIL: {} old mails removed", count),
+            Ok(Err(err)) => error!("MAIL: cannot remove the old mails: {}", err),
+            Err(err) => error!("MAIL: cannot remove the old mails: {}", err),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use actix_web::web;
+use sproot::models::AuthPool;
+
+use crate::Pool;
+
+pub mod deletion;
+pub mod export;
+pub mod mail_queue;
+pub mod rate_limits;
+
+/// Spawn the background jobs on the current (actix) runtime
+pub fn start_jobs(pool: &Pool) {
+    let db = web::Data::new(AuthPool { pool: pool.clone() });
+
+    actix_web::rt::spawn(deletion::deletion_job(db.clone()));
+    actix_web::rt::spawn(export::export_job(db.clone()));
+    actix_web::rt::spawn(export::cleanup_job(db.clone()));
+    actix_web::rt::spawn(mail_queue::delivery_job(db.clone()));
+    actix_web::rt::spawn(mail_queue::cleanup_job(db.clone()));
+    actix_web::rt::spawn(rate_limits::cleanup_job(db));
+}
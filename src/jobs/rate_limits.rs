@@ -0,0 +1,36 @@
+use actix_web::{rt::time, web};
+use sproot::models::AuthPool;
+
+use crate::{models::RateCounter, utils::pow::CHALLENGE_VALIDITY_SECS, CONFIG};
+
+// How often the stale rate limit counters are removed
+const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Remove the counters whose window is over for every limit
+pub async fn cleanup_job(db: web::Data<AuthPool>) {
+    let limits = &CONFIG.rate_limits;
+    let max_window = [
+        limits.sso_per_ip,
+        limits.sso_per_email,
+        limits.sso_per_recipient,
+        limits.key_create_per_customer,
+    ]
+    .iter()
+    .map(|window| window.window_secs)
+    .chain([CONFIG.pow.window_secs, CHALLENGE_VALIDITY_SECS])
+    .max()
+    .unwrap_or(0);
+
+    let mut interval = time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let dbc = db.clone();
+        match web::block(move || RateCounter::delete_stale(&mut dbc.pool.get()?, max_window)).await
+        {
+            Ok(Ok(count)) => trace!("RATE: {} stale counters removed", count),
+            Ok(Err(err)) => error!("RATE: cannot remove the stale counters: {}", err),
+            Err(err) => error!("RATE: cannot remove the stale counters: {}", err),
+        }
+    }
+}
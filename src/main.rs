@@ -12,13 +12,19 @@ use jsonwebtoken::{DecodingKey, EncodingKey};
 use once_cell::sync::Lazy;
 use sproot::prog;
 
-use crate::utils::config::Config;
+use crate::utils::{
+    config::Config,
+    jwt::{load_decoding_key, load_encoding_key},
+};
 
 mod api;
 mod flow_run;
+mod jobs;
+mod models;
 mod routes;
 mod server;
 mod utils;
+mod xschema;
 
 // Helper types for less boilerplate code
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
@@ -43,16 +49,24 @@ static CONFIG: Lazy<Config> = Lazy::new(|| match Config::new() {
     }
 });
 
-static JWT_ENCODINGKEY: Lazy<EncodingKey> = Lazy::new(|| {
-    let content = std::fs::read(&CONFIG.jwt_ec_priv).unwrap();
-    let secret = String::from_utf8_lossy(&content);
-    EncodingKey::from_ec_pem(secret.as_bytes()).unwrap()
+// Lazy static of the keys signing/verifying the tokens, loaded for jwt.algorithm
+static JWT_ENCODINGKEY: Lazy<EncodingKey> = Lazy::new(|| match load_encoding_key() {
+    Ok(key) => key,
+    Err(err) => {
+        error!(
+            "Cannot load the jwt private key {}: {}",
+            CONFIG.jwt_priv, err
+        );
+        std::process::exit(1);
+    }
 });
 
-static JWT_DECODINGKEY: Lazy<DecodingKey> = Lazy::new(|| {
-    let content = std::fs::read(&CONFIG.jwt_ec_pub).unwrap();
-    let secret = String::from_utf8_lossy(&content);
-    DecodingKey::from_ec_pem(secret.as_bytes()).unwrap()
+static JWT_DECODINGKEY: Lazy<DecodingKey> = Lazy::new(|| match load_decoding_key() {
+    Ok(key) => key,
+    Err(err) => {
+        error!("Cannot load the jwt public key {}: {}", CONFIG.jwt_pub, err);
+        std::process::exit(1);
+    }
 });
 
 // Embed migrations into the binary
@@ -0,0 +1,481 @@
+use std::{collections::HashMap, str::FromStr};
+
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::{now, IntervalDsl},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use super::{
+    AuditLog, CustomerSession, OrgApiKey, OrgInvitation, OrgMember, OrgRole, Organization,
+};
+use crate::{
+    utils::{email::normalize_email, i18n::Locale, notifications::NotificationKind},
+    xschema::customers,
+    ConnType, CONFIG,
+};
+
+/// View of a customer's row from the ssot point of view
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct Account {
+    pub id: Uuid,
+    pub email: String,
+    pub status: String,
+    pub plan: String,
+    pub status_changed_at: Option<NaiveDateTime>,
+    /// Preferred language tag for the mails, see Locale::resolve
+    pub locale: Option<String>,
+    /// Kinds of the security notifications the customer opted out of
+    pub notification_optouts: Vec<String>,
+    /// See Deliverability, updated by the bounces and complaints
+    pub deliverability: String,
+    pub deliverability_reason: Option<String>,
+    pub deliverability_changed_at: Option<NaiveDateTime>,
+    /// Set while the customer is suspended, whatever his status
+    pub suspended_at: Option<NaiveDateTime>,
+}
+
+/// Status of a customer's account, a suspension being tracked apart
+/// (suspended_at) so that it doesn't override a pending deletion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    Active,
+    #[serde(rename = "pending-deletion")]
+    PendingDeletion,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::PendingDeletion => "pending-deletion",
+        }
+    }
+}
+
+/// Deliverability of a customer's email, ordered by severity: a report
+/// only replaces the state if it's at least as severe (a soft bounce
+/// never clears a hard bounce).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Deliverability {
+    Ok,
+    SoftBounce,
+    Complaint,
+    HardBounce,
+}
+
+impl Deliverability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Deliverability::Ok => "ok",
+            Deliverability::SoftBounce => "soft-bounce",
+            Deliverability::Complaint => "complaint",
+            Deliverability::HardBounce => "hard-bounce",
+        }
+    }
+}
+
+impl FromStr for Deliverability {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ok" => Ok(Deliverability::Ok),
+            "soft-bounce" => Ok(Deliverability::SoftBounce),
+            "complaint" => Ok(Deliverability::Complaint),
+            "hard-bounce" => Ok(Deliverability::HardBounce),
+            _ => {
+                error!("Deliverability: unknown state {}", s);
+                Err(ApiError::ServerError(None))
+            }
+        }
+    }
+}
+
+/// Error returned for every action refused to a suspended customer,
+/// distinct from the generic AuthorizationError so it can be shown as is.
+pub fn suspended_error() -> ApiError {
+    ApiError::AuthorizationError(Some("account_suspended".to_owned()))
+}
+
+/// Error returned when no mail is sent as the customer's email hard bounced
+/// (and the cooldown before retrying isn't over)
+pub fn undeliverable_error() -> ApiError {
+    ApiError::InvalidRequestError(Some("email_undeliverable".to_owned()))
+}
+
+impl Account {
+    pub fn get(conn: &mut ConnType, cid: &Uuid) -> Result<Self, ApiError> {
+        Ok(customers::table.find(cid).first(conn)?)
+    }
+
+    pub fn find(conn: &mut ConnType, cid: &Uuid) -> Result<Option<Self>, ApiError> {
+        Ok(customers::table.find(cid).first(conn).optional()?)
+    }
+
+    /// Get the customer by his (normalized) email, the stored
+    /// ones being normalized at startup (normalize_stored_emails).
+    pub fn get_by_email(conn: &mut ConnType, email: &str) -> Result<Option<Self>, ApiError> {
+        Ok(customers::table
+            .filter(customers::email.eq(email))
+            .first(conn)
+            .optional()?)
+    }
+
+    /// Normalize the stored emails with the current email_local_part policy, the
+    /// ones stored before (or under another policy) being matched by none of the
+    /// lookups otherwise. Fail without changing anything if several customers
+    /// share a same normalized email, which must be merged by hand.
+    pub fn normalize_stored_emails(conn: &mut ConnType) -> Result<usize, ApiError> {
+        conn.transaction(|conn| {
+            let emails: Vec<(Uuid, String)> = customers::table
+                .select((customers::id, customers::email))
+                .load(conn)?;
+
+            let mut by_normalized: HashMap<String, Vec<(Uuid, String)>> = HashMap::new();
+            for (cid, email) in emails {
+                match normalize_email(&email) {
+                    Ok(normalized) => by_normalized
+                        .entry(normalized)
+                        .or_default()
+                        .push((cid, email)),
+                    Err(_) => warn!("EMAIL: cannot normalize the email of {}", cid),
+                }
+            }
+
+            let duplicates: Vec<String> = by_normalized
+                .iter()
+                .filter(|(_, customers)| customers.len() > 1)
+                .map(|(normalized, customers)| {
+                    let ids: Vec<String> =
+                        customers.iter().map(|(cid, _)| cid.to_string()).collect();
+                    format!("{} ({})", normalized, ids.join(", "))
+                })
+                .collect();
+            if !duplicates.is_empty() {
+                error!(
+                    "EMAIL: customers with duplicate normalized emails: {}",
+                    duplicates.join("; ")
+                );
+                return Err(ApiError::ServerError(None));
+            }
+
+            let mut updated = 0;
+            for (normalized, customers) in by_normalized {
+                let (cid, email) = &customers[0];
+                if *email != normalized {
+                    updated += diesel::update(customers::table.find(cid))
+                        .set(customers::email.eq(&normalized))
+                        .execute(conn)?;
+                }
+            }
+
+            Ok(updated)
+        })
+    }
+
+    /// Search the customers whose email contains the pattern (and
+    /// is in the deliverability state if any)
+    pub fn search(
+        conn: &mut ConnType,
+        pattern: &str,
+        deliverability: Option<Deliverability>,
+        size: i64,
+        page: i64,
+    ) -> Result<Vec<Self>, ApiError> {
+        // Escape the LIKE wildcards, the pattern is a plain substring
+        let escaped = pattern
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+
+        let mut query = customers::table
+            .filter(customers::email.ilike(format!("%{}%", escaped)))
+            .into_boxed();
+        if let Some(state) = deliverability {
+            query = query.filter(customers::deliverability.eq(state.as_str()));
+        }
+
+        Ok(query
+            .order(customers::email.asc())
+            .limit(size)
+            .offset(page * size)
+            .load(conn)?)
+    }
+
+    /// Locale of the mails sent to the customer
+    pub fn locale(&self) -> Locale {
+        Locale::resolve(self.locale.as_deref())
+    }
+
+    pub fn set_locale(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        locale: Option<&str>,
+    ) -> Result<usize, ApiError> {
+        Ok(diesel::update(customers::table.find(cid))
+            .set(customers::locale.eq(locale))
+            .execute(conn)?)
+    }
+
+    /// Does the customer want to receive the notifications of this kind,
+    /// the critical ones being always sent unless the email hard bounced.
+    pub fn wants_notification(&self, kind: NotificationKind) -> bool {
+        match self.deliverability() {
+            Deliverability::HardBounce => false,
+            _ if kind.is_critical() => true,
+            Deliverability::Complaint => false,
+            _ => !self.notification_optouts.iter().any(|k| k == kind.as_str()),
+        }
+    }
+
+    pub fn deliverability(&self) -> Deliverability {
+        self.deliverability.parse().unwrap_or(Deliverability::Ok)
+    }
+
+    /// Can the customer's email no longer receive mails (hard bounce),
+    /// see retry_delivery for the magic links
+    pub fn is_undeliverable(&self) -> bool {
+        self.deliverability() == Deliverability::HardBounce
+    }
+
+    /// Allow a new attempt to deliver to the hard bounced email of the customer
+    /// if the last bounce (or attempt) is older than bounce_retry_hours, so that
+    /// a wrong report doesn't stop the magic links for good. Return whether the
+    /// mail can be sent, the attempt restarting the cooldown.
+    pub fn retry_delivery(conn: &mut ConnType, cid: &Uuid) -> Result<bool, ApiError> {
+        let updated = diesel::update(
+            customers::table.find(cid).filter(
+                customers::deliverability
+                    .eq(Deliverability::HardBounce.as_str())
+                    .and(
+                        customers::deliverability_changed_at
+                            .lt((now - CONFIG.bounce_retry_hours.hours()).nullable())
+                            .or(customers::deliverability_changed_at.is_null()),
+                    ),
+            ),
+        )
+        .set(customers::deliverability_changed_at.eq(now))
+        .execute(conn)?;
+
+        Ok(updated == 1)
+    }
+
+    /// Record the bounce (or complaint) reported for the email, if as severe as
+    /// the current state, and return the id of the customer owning the email.
+    pub fn record_bounce(
+        conn: &mut ConnType,
+        email: &str,
+        state: Deliverability,
+        reason: Option<&str>,
+    ) -> Result<Option<Uuid>, ApiError> {
+        conn.transaction(|conn| {
+            let account = match Self::get_by_email(conn, email)? {
+                Some(account) => account,
+                None => return Ok(None),
+            };
+
+            if state >= account.deliverability() {
+                Self::set_deliverability(conn, &account.id, state, reason)?;
+            }
+            Ok(Some(account.id))
+        })
+    }
+
+    pub fn set_deliverability(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        state: Deliverability,
+        reason: Option<&str>,
+    ) -> Result<usize, ApiError> {
+        Ok(diesel::update(customers::table.find(cid))
+            .set((
+                customers::deliverability.eq(state.as_str()),
+                customers::deliverability_reason.eq(reason),
+                customers::deliverability_changed_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)?)
+    }
+
+    pub fn set_notification_optouts(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        kinds: &[NotificationKind],
+    ) -> Result<usize, ApiError> {
+        let optouts: Vec<&str> = kinds.iter().map(NotificationKind::as_str).collect();
+        Ok(diesel::update(customers::table.find(cid))
+            .set(customers::notification_optouts.eq(optouts))
+            .execute(conn)?)
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_at.is_some()
+    }
+
+    /// Get the accounts pending deletion for more than grace_days
+    pub fn get_expired_deletions(
+        conn: &mut ConnType,
+        grace_days: i64,
+    ) -> Result<Vec<Self>, ApiError> {
+        Ok(customers::table
+            .filter(
+                customers::status
+                    .eq(AccountStatus::PendingDeletion.as_str())
+                    .and(customers::status_changed_at.lt((now - grace_days.days()).nullable())),
+            )
+            .load(conn)?)
+    }
+
+    /// Permanently delete the customer along with his sessions, invitations,
+    /// the organizations he's the only member of and their keys. The keys he
+    /// created in the organizations shared with others are handed over to
+    /// them, and his entries of the audit log are pseudonymised.
+    pub fn delete_with_data(conn: &mut ConnType, cid: &Uuid) -> Result<usize, ApiError> {
+        conn.transaction(|conn| {
+            let account = Self::get(conn, cid)?;
+            let oids = OrgMember::get_sole_member_orgs(conn, cid)?;
+
+            // He may have become the last owner of a shared organization since
+            // he asked for the deletion, promote another member to replace him.
+            for (oid, _, _) in OrgMember::get_by_customer(conn, cid)? {
+                if oids.contains(&oid) || OrgMember::lock_owners(conn, &oid)? != 1 {
+                    continue;
+                }
+                if OrgMember::get_role(conn, &oid, cid)? == Some(OrgRole::Owner) {
+                    if let Some(successor) = OrgMember::promote_successor(conn, &oid, cid)? {
+                        info!("DELETION: {} is now an owner of {}", successor, oid);
+                    }
+                }
+            }
+
+            OrgApiKey::delete_for_customer(conn, cid, &oids)?;
+            CustomerSession::delete_all(conn, cid)?;
+            OrgInvitation::delete_by_customer(conn, cid, &account.email)?;
+            Organization::delete_many(conn, &oids)?;
+            AuditLog::pseudonymize_customer(conn, cid, &account.email)?;
+            // The remaining memberships are removed on delete cascade
+            Ok(diesel::delete(customers::table.find(cid)).execute(conn)?)
+        })
+    }
+
+    /// Return the account_suspended error if the customer is suspended
+    pub fn ensure_not_suspended(&self) -> Result<(), ApiError> {
+        if self.is_suspended() {
+            Err(suspended_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Set the status of the customer only if it currently is `from`,
+    /// return 0 otherwise.
+    pub fn transition_status(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        from: AccountStatus,
+        to: AccountStatus,
+    ) -> Result<usize, ApiError> {
+        Ok(diesel::update(
+            customers::table
+                .find(cid)
+                .filter(customers::status.eq(from.as_str())),
+        )
+        .set((
+            customers::status.eq(to.as_str()),
+            customers::status_changed_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)?)
+    }
+
+    /// Suspend the customer, revoking all his sessions and, if disable_keys
+    /// is set, disabling all his keys. His status is left as is, a pending
+    /// deletion still happening at the end of its grace period.
+    pub fn suspend(conn: &mut ConnType, cid: &Uuid, disable_keys: bool) -> Result<usize, ApiError> {
+        conn.transaction(|conn| {
+            let updated = diesel::update(customers::table.find(cid))
+                .set(customers::suspended_at.eq(now.nullable()))
+                .execute(conn)?;
+            CustomerSession::revoke_all(conn, cid)?;
+            if disable_keys {
+                OrgApiKey::set_disabled_for_customer(conn, cid, true)?;
+            }
+
+            Ok(updated)
+        })
+    }
+
+    /// Reactivate the suspended customer and, if enable_keys is set, enable
+    /// all his keys. Return 0 if he isn't suspended (a pending deletion is
+    /// left as is).
+    pub fn unsuspend(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        enable_keys: bool,
+    ) -> Result<usize, ApiError> {
+        conn.transaction(|conn| {
+            let updated = diesel::update(
+                customers::table
+                    .find(cid)
+                    .filter(customers::suspended_at.is_not_null()),
+            )
+            .set(customers::suspended_at.eq(None::<NaiveDateTime>))
+            .execute(conn)?;
+            if updated > 0 && enable_keys {
+                OrgApiKey::set_disabled_for_customer(conn, cid, false)?;
+            }
+
+            Ok(updated)
+        })
+    }
+
+    pub fn set_plan(conn: &mut ConnType, cid: &Uuid, plan: &str) -> Result<usize, ApiError> {
+        Ok(diesel::update(customers::table.find(cid))
+            .set(customers::plan.eq(plan))
+            .execute(conn)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(status: AccountStatus) -> Account {
+        Account {
+            id: Uuid::nil(),
+            email: "jane@example.com".to_owned(),
+            status: status.as_str().to_owned(),
+            plan: "free".to_owned(),
+            status_changed_at: None,
+            locale: None,
+            notification_optouts: Vec::new(),
+            deliverability: Deliverability::Ok.as_str().to_owned(),
+            deliverability_reason: None,
+            deliverability_changed_at: None,
+            suspended_at: None,
+        }
+    }
+
+    #[test]
+    fn suspension_keeps_pending_deletion() {
+        // Deletion requested, then suspend and unsuspend only set and clear suspended_at
+        let mut account = account(AccountStatus::PendingDeletion);
+        account.suspended_at = Some(NaiveDateTime::default());
+        assert!(account.is_suspended());
+        assert!(account.ensure_not_suspended().is_err());
+        assert_eq!(account.status, AccountStatus::PendingDeletion.as_str());
+
+        account.suspended_at = None;
+        assert!(account.ensure_not_suspended().is_ok());
+        assert_eq!(account.status, AccountStatus::PendingDeletion.as_str());
+    }
+
+    #[test]
+    fn active_is_not_suspended() {
+        assert!(!account(AccountStatus::Active).is_suspended());
+    }
+}
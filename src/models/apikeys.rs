@@ -0,0 +1,207 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use super::{Account, OrgMember};
+use crate::{xschema::apikeys, ConnType};
+
+/// ApiKey as owned by an organization, customer_id being the creator
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct OrgApiKey {
+    pub id: i64,
+    pub key: String,
+    pub host_uuid: Option<String>,
+    pub customer_id: Uuid,
+    pub berta: String,
+    pub org_id: Uuid,
+    pub disabled_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = apikeys)]
+pub struct OrgApiKeyDTO {
+    pub key: String,
+    pub host_uuid: Option<String>,
+    pub customer_id: Uuid,
+    pub berta: String,
+    pub org_id: Uuid,
+}
+
+impl OrgApiKey {
+    /// Mask the key, only keeping its first characters to identify it
+    pub fn redacted(mut self) -> Self {
+        self.key = format!("{}...", self.key.chars().take(4).collect::<String>());
+        self
+    }
+
+    /// Get the keys across all customers, optionally filtered by
+    /// the creator (cid) and/or the owning organization (oid)
+    pub fn search(
+        conn: &mut ConnType,
+        cid: Option<Uuid>,
+        oid: Option<Uuid>,
+        size: i64,
+        page: i64,
+    ) -> Result<Vec<Self>, ApiError> {
+        let mut query = apikeys::table.into_boxed();
+
+        if let Some(cid) = cid {
+            query = query.filter(apikeys::customer_id.eq(cid));
+        }
+        if let Some(oid) = oid {
+            query = query.filter(apikeys::org_id.eq(oid));
+        }
+
+        Ok(query
+            .order(apikeys::id.asc())
+            .limit(size)
+            .offset(page * size)
+            .load(conn)?)
+    }
+
+    pub fn get_by_org(
+        conn: &mut ConnType,
+        oid: &Uuid,
+        size: i64,
+        page: i64,
+    ) -> Result<Vec<Self>, ApiError> {
+        Ok(apikeys::table
+            .filter(apikeys::org_id.eq(oid))
+            .order(apikeys::id.asc())
+            .limit(size)
+            .offset(page * size)
+            .load(conn)?)
+    }
+
+    /// Get the keys created by the customer and the ones of his personal organization
+    pub fn get_by_customer(conn: &mut ConnType, cid: &Uuid) -> Result<Vec<Self>, ApiError> {
+        Ok(apikeys::table
+            .filter(apikeys::customer_id.eq(cid).or(apikeys::org_id.eq(cid)))
+            .order(apikeys::id.asc())
+            .load(conn)?)
+    }
+
+    pub fn get_by_id_and_org(conn: &mut ConnType, oid: &Uuid, kid: i64) -> Result<Self, ApiError> {
+        Ok(apikeys::table
+            .filter(apikeys::id.eq(kid).and(apikeys::org_id.eq(oid)))
+            .first(conn)?)
+    }
+
+    pub fn get_by_key(conn: &mut ConnType, key: &str) -> Result<Self, ApiError> {
+        Ok(apikeys::table.filter(apikeys::key.eq(key)).first(conn)?)
+    }
+
+    /// Get the key which have the key == sptk only if it can still be used,
+    /// which means it's not disabled and its creator is not suspended.
+    pub fn get_usable_by_key(conn: &mut ConnType, key: &str) -> Result<Self, ApiError> {
+        let api_key = Self::get_by_key(conn, key)?;
+        if api_key.is_disabled() {
+            return Err(ApiError::AuthorizationError(Some(
+                "key_disabled".to_owned(),
+            )));
+        }
+        Account::get(conn, &api_key.customer_id)?.ensure_not_suspended()?;
+
+        Ok(api_key)
+    }
+
+    pub fn get_by_key_and_org(
+        conn: &mut ConnType,
+        oid: &Uuid,
+        key: &str,
+    ) -> Result<Option<Self>, ApiError> {
+        Ok(apikeys::table
+            .filter(apikeys::key.eq(key).and(apikeys::org_id.eq(oid)))
+            .first(conn)
+            .optional()?)
+    }
+
+    /// Set (or clear with None) the host bound to the key
+    pub fn set_host(
+        conn: &mut ConnType,
+        kid: i64,
+        host_uuid: Option<&str>,
+    ) -> Result<usize, ApiError> {
+        Ok(diesel::update(apikeys::table.find(kid))
+            .set(apikeys::host_uuid.eq(host_uuid))
+            .execute(conn)?)
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+
+    /// Disable (or enable) the keys of the customer, which are the keys
+    /// he created and the ones of his personal organization (same id).
+    pub fn set_disabled_for_customer(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        disabled: bool,
+    ) -> Result<usize, ApiError> {
+        let target =
+            apikeys::table.filter(apikeys::customer_id.eq(cid).or(apikeys::org_id.eq(cid)));
+
+        Ok(if disabled {
+            diesel::update(target.filter(apikeys::disabled_at.is_null()))
+                .set(apikeys::disabled_at.eq(diesel::dsl::now))
+                .execute(conn)?
+        } else {
+            diesel::update(target)
+                .set(apikeys::disabled_at.eq(None::<NaiveDateTime>))
+                .execute(conn)?
+        })
+    }
+
+    /// Delete the keys of the organizations (oids) the customer is the only
+    /// member of, and hand the keys he created in the other organizations
+    /// over to their first owner so that they keep working.
+    pub fn delete_for_customer(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        oids: &[Uuid],
+    ) -> Result<usize, ApiError> {
+        let mut deleted =
+            diesel::delete(apikeys::table.filter(apikeys::org_id.eq_any(oids))).execute(conn)?;
+
+        let shared: Vec<Uuid> = apikeys::table
+            .filter(apikeys::customer_id.eq(cid))
+            .select(apikeys::org_id)
+            .distinct()
+            .load(conn)?;
+        for oid in shared {
+            deleted += Self::hand_over(conn, cid, &oid)?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Make the first other owner of the organization the creator of the keys
+    /// the customer created in it, or delete them if there's none. Return the
+    /// number of keys deleted.
+    pub fn hand_over(conn: &mut ConnType, cid: &Uuid, oid: &Uuid) -> Result<usize, ApiError> {
+        let created =
+            apikeys::table.filter(apikeys::customer_id.eq(cid).and(apikeys::org_id.eq(oid)));
+
+        match OrgMember::get_owners(conn, oid)?
+            .into_iter()
+            .find(|owner| owner != cid)
+        {
+            Some(owner) => {
+                diesel::update(created)
+                    .set(apikeys::customer_id.eq(owner))
+                    .execute(conn)?;
+                Ok(0)
+            }
+            // Nobody left to hand them over to
+            None => Ok(diesel::delete(created).execute(conn)?),
+        }
+    }
+
+    pub fn insert_and_get(conn: &mut ConnType, entry: &OrgApiKeyDTO) -> Result<Self, ApiError> {
+        Ok(diesel::insert_into(apikeys::table)
+            .values(entry)
+            .get_result(conn)?)
+    }
+}
@@ -0,0 +1,140 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    prelude::*,
+    sql_types::{Int8, Text, Uuid as SqlUuid},
+};
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use crate::{xschema::audit_logs, ConnType};
+
+/// Number of entries updated by pseudonymize_audit_logs
+#[derive(QueryableByName, Debug)]
+struct Pseudonymized {
+    #[diesel(sql_type = Int8)]
+    count: i64,
+}
+
+/// Single entry of the (append-only) audit log
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct AuditLog {
+    pub id: i64,
+    pub customer_id: Option<Uuid>,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub success: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = audit_logs)]
+pub struct AuditLogDTO {
+    pub customer_id: Option<Uuid>,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub success: bool,
+}
+
+/// Optional filters used by the admin to search across customers
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AuditFilter {
+    pub customer_id: Option<Uuid>,
+    pub action: Option<String>,
+    pub actor: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+impl AuditLog {
+    pub fn insert(conn: &mut ConnType, entry: &AuditLogDTO) -> Result<usize, ApiError> {
+        Ok(diesel::insert_into(audit_logs::table)
+            .values(entry)
+            .execute(conn)?)
+    }
+
+    /// Get the events of a specific customer, most recent first
+    pub fn get_by_customer(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        size: i64,
+        page: i64,
+    ) -> Result<Vec<Self>, ApiError> {
+        Ok(audit_logs::table
+            .filter(audit_logs::customer_id.eq(cid))
+            .order(audit_logs::created_at.desc())
+            .limit(size)
+            .offset(page * size)
+            .load(conn)?)
+    }
+
+    pub fn get_all_by_customer(conn: &mut ConnType, cid: &Uuid) -> Result<Vec<Self>, ApiError> {
+        Ok(audit_logs::table
+            .filter(audit_logs::customer_id.eq(cid))
+            .order(audit_logs::created_at.desc())
+            .load(conn)?)
+    }
+
+    pub fn count_by_customer(conn: &mut ConnType, cid: &Uuid) -> Result<i64, ApiError> {
+        Ok(audit_logs::table
+            .filter(audit_logs::customer_id.eq(cid))
+            .count()
+            .get_result(conn)?)
+    }
+
+    /// Get the events matching the filter across all customers, most recent first
+    pub fn search(
+        conn: &mut ConnType,
+        filter: &AuditFilter,
+        size: i64,
+        page: i64,
+    ) -> Result<Vec<Self>, ApiError> {
+        let mut query = audit_logs::table.into_boxed();
+
+        if let Some(cid) = filter.customer_id {
+            query = query.filter(audit_logs::customer_id.eq(cid));
+        }
+        if let Some(action) = &filter.action {
+            query = query.filter(audit_logs::action.eq(action));
+        }
+        if let Some(actor) = &filter.actor {
+            query = query.filter(audit_logs::actor.eq(actor));
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(audit_logs::created_at.ge(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(audit_logs::created_at.le(until));
+        }
+
+        Ok(query
+            .order(audit_logs::created_at.desc())
+            .limit(size)
+            .offset(page * size)
+            .load(conn)?)
+    }
+
+    /// Remove the personal data of the deleted customer from the log: the ip
+    /// and user agent of his entries are cleared and his emails (as actor or
+    /// target) replaced by a pseudonym, the entries themselves being kept.
+    /// Done by pseudonymize_audit_logs, the only update the log allows.
+    pub fn pseudonymize_customer(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        email: &str,
+    ) -> Result<usize, ApiError> {
+        let updated: Pseudonymized =
+            diesel::sql_query("SELECT pseudonymize_audit_logs($1, $2) AS count")
+                .bind::<SqlUuid, _>(cid)
+                .bind::<Text, _>(email)
+                .get_result(conn)?;
+
+        Ok(updated.count as usize)
+    }
+}
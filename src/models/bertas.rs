@@ -0,0 +1,69 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+
+use crate::{
+    xschema::{apikeys, bertas},
+    ConnType,
+};
+
+/// Berta instance to which the hosts' data are sent
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct Berta {
+    pub name: String,
+    pub enabled: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, AsChangeset, Debug, Serialize, Deserialize)]
+#[diesel(table_name = bertas)]
+pub struct BertaDTO {
+    pub name: String,
+    pub enabled: bool,
+}
+
+impl Berta {
+    pub fn get_all(conn: &mut ConnType) -> Result<Vec<Self>, ApiError> {
+        Ok(bertas::table.order(bertas::name.asc()).load(conn)?)
+    }
+
+    /// Insert the berta or update it if it already exists
+    pub fn upsert(conn: &mut ConnType, entry: &BertaDTO) -> Result<Self, ApiError> {
+        Ok(diesel::insert_into(bertas::table)
+            .values(entry)
+            .on_conflict(bertas::name)
+            .do_update()
+            .set(entry)
+            .get_result(conn)?)
+    }
+
+    pub fn delete(conn: &mut ConnType, target: &str) -> Result<usize, ApiError> {
+        Ok(diesel::delete(bertas::table.find(target)).execute(conn)?)
+    }
+
+    /// Return the enabled berta with the fewest keys
+    pub fn least_occupied(conn: &mut ConnType) -> Result<String, ApiError> {
+        let enabled: Vec<String> = bertas::table
+            .filter(bertas::enabled.eq(true))
+            .select(bertas::name)
+            .load(conn)?;
+        let occupation: Vec<(String, i64)> = apikeys::table
+            .group_by(apikeys::berta)
+            .select((apikeys::berta, diesel::dsl::count_star()))
+            .load(conn)?;
+
+        enabled
+            .into_iter()
+            .min_by_key(|name| {
+                occupation
+                    .iter()
+                    .find(|(berta, _)| berta == name)
+                    .map_or(0, |(_, count)| *count)
+            })
+            .ok_or_else(|| {
+                error!("Berta: no enabled berta to assign the key to");
+                ApiError::ServerError(None)
+            })
+    }
+}
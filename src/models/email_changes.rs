@@ -0,0 +1,137 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::{now, IntervalDsl},
+    prelude::*,
+    result::{DatabaseErrorKind, Error},
+    upsert::excluded,
+};
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use super::{Account, Deliverability};
+use crate::{
+    xschema::{customers, email_changes},
+    ConnType,
+};
+
+/// Pending change of the customer's email, only applied
+/// once the new address has been confirmed.
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct EmailChange {
+    pub id: Uuid,
+    pub customer_id: Uuid,
+    pub new_email: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = email_changes)]
+pub struct EmailChangeDTO<'a> {
+    pub customer_id: Uuid,
+    pub new_email: &'a str,
+}
+
+/// Error returned when the email is already used by another customer
+pub fn email_taken_error() -> ApiError {
+    ApiError::InvalidRequestError(Some("email_taken".to_owned()))
+}
+
+impl EmailChange {
+    pub fn get(conn: &mut ConnType, eid: &Uuid) -> Result<Option<Self>, ApiError> {
+        Ok(email_changes::table.find(eid).first(conn).optional()?)
+    }
+
+    /// Get the change (eid) if it's neither replaced nor expired
+    pub fn get_pending(conn: &mut ConnType, eid: &Uuid) -> Result<Option<Self>, ApiError> {
+        Ok(email_changes::table
+            .find(eid)
+            .filter(email_changes::expires_at.gt(now))
+            .first(conn)
+            .optional()?)
+    }
+
+    /// Create the pending change, replacing the previous one of the customer,
+    /// valid for validity_hours from now as seen by the database.
+    pub fn upsert(
+        conn: &mut ConnType,
+        entry: &EmailChangeDTO,
+        validity_hours: i64,
+    ) -> Result<Self, ApiError> {
+        Ok(diesel::insert_into(email_changes::table)
+            .values((
+                entry,
+                email_changes::expires_at.eq(now + validity_hours.hours()),
+            ))
+            .on_conflict(email_changes::customer_id)
+            .do_update()
+            .set((
+                email_changes::id.eq(diesel::dsl::sql::<diesel::sql_types::Uuid>(
+                    "gen_random_uuid()",
+                )),
+                email_changes::new_email.eq(excluded(email_changes::new_email)),
+                email_changes::created_at.eq(now),
+                email_changes::expires_at.eq(excluded(email_changes::expires_at)),
+            ))
+            .get_result(conn)?)
+    }
+
+    pub fn delete(conn: &mut ConnType, eid: &Uuid) -> Result<usize, ApiError> {
+        Ok(diesel::delete(email_changes::table.find(eid)).execute(conn)?)
+    }
+
+    /// Update the email of the customer (resetting its deliverability)
+    /// and remove the pending change.
+    /// Fail with email_taken if another customer got the address since.
+    pub fn apply(&self, conn: &mut ConnType) -> Result<(), ApiError> {
+        conn.transaction(|conn| {
+            if matches!(Account::get_by_email(conn, &self.new_email)?, Some(other) if other.id != self.customer_id)
+            {
+                return Err(email_taken_error());
+            }
+
+            set_email(conn, &self.customer_id, &self.new_email)?;
+            Self::delete(conn, &self.id)?;
+            Ok(())
+        })
+    }
+
+    /// Restore the email the customer had before a change he didn't make,
+    /// dropping any pending change. Return false (changing nothing) if
+    /// another customer got the previous email since.
+    pub fn revert(conn: &mut ConnType, cid: &Uuid, previous_email: &str) -> Result<bool, ApiError> {
+        conn.transaction(|conn| {
+            if matches!(Account::get_by_email(conn, previous_email)?, Some(other) if other.id != *cid)
+            {
+                return Ok(false);
+            }
+
+            diesel::delete(email_changes::table.filter(email_changes::customer_id.eq(cid)))
+                .execute(conn)?;
+            set_email(conn, cid, previous_email)?;
+            Ok(true)
+        })
+    }
+}
+
+/// Update the email of the customer, resetting its deliverability as
+/// the bounces of the previous email no longer apply to the new one.
+/// The UNIQUE constraint is the last guard against a concurrent change.
+fn set_email(conn: &mut ConnType, cid: &Uuid, email: &str) -> Result<(), ApiError> {
+    match diesel::update(customers::table.find(cid))
+        .set((
+            customers::email.eq(email),
+            customers::deliverability.eq(Deliverability::Ok.as_str()),
+            customers::deliverability_reason.eq(None::<String>),
+            customers::deliverability_changed_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)
+    {
+        Ok(_) => Ok(()),
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(email_taken_error())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,193 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::{
+    dsl::{now, IntervalDsl},
+    prelude::*,
+    sql_types::Int4,
+};
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use super::{Account, AuditLog, CustomerSession, OrgApiKey, OrgMember};
+use crate::{xschema::data_exports, ConnType};
+
+/// Export of the customer's data generated by the export job, the archive
+/// (JSON) being only set once the export is ready and loaded on its own.
+#[derive(Queryable, QueryableByName, Selectable, Debug, Serialize, Deserialize)]
+#[diesel(table_name = data_exports)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub customer_id: Uuid,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub claimed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+impl ExportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportStatus::Pending => "pending",
+            ExportStatus::Ready => "ready",
+            ExportStatus::Failed => "failed",
+        }
+    }
+}
+
+impl DataExport {
+    /// Queue the export of the customer, downloadable for validity_hours
+    pub fn create(conn: &mut ConnType, cid: &Uuid, validity_hours: i64) -> Result<Self, ApiError> {
+        Ok(diesel::insert_into(data_exports::table)
+            .values((
+                data_exports::customer_id.eq(cid),
+                data_exports::expires_at.eq(now + validity_hours.hours()),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)?)
+    }
+
+    /// Get the archive of the export if it's ready and not expired
+    pub fn get_ready_archive(
+        conn: &mut ConnType,
+        eid: &Uuid,
+        cid: &Uuid,
+    ) -> Result<Option<Vec<u8>>, ApiError> {
+        let archive: Option<Option<Vec<u8>>> = data_exports::table
+            .filter(
+                data_exports::id
+                    .eq(eid)
+                    .and(data_exports::customer_id.eq(cid))
+                    .and(data_exports::status.eq(ExportStatus::Ready.as_str()))
+                    .and(data_exports::expires_at.gt(now)),
+            )
+            .select(data_exports::archive)
+            .first(conn)
+            .optional()?;
+
+        Ok(archive.flatten())
+    }
+
+    /// Claim the oldest pending export to generate it, skipping the ones being
+    /// generated by another worker unless claimed more than lease_secs ago (the
+    /// worker having stopped before completing it).
+    pub fn claim_next(conn: &mut ConnType, lease_secs: i32) -> Result<Option<Self>, ApiError> {
+        Ok(diesel::sql_query(
+            "UPDATE data_exports SET claimed_at = NOW()
+            WHERE id IN (
+                SELECT id FROM data_exports
+                WHERE status = 'pending' AND expires_at > NOW()
+                    AND (claimed_at IS NULL OR claimed_at < NOW() - $1 * INTERVAL '1 second')
+                ORDER BY created_at LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, customer_id, status, created_at, expires_at, claimed_at",
+        )
+        .bind::<Int4, _>(lease_secs)
+        .get_result(conn)
+        .optional()?)
+    }
+
+    /// Store the archive (or the failure if None) of the export
+    pub fn complete(
+        conn: &mut ConnType,
+        eid: &Uuid,
+        archive: Option<Vec<u8>>,
+    ) -> Result<usize, ApiError> {
+        let status = match archive {
+            Some(_) => ExportStatus::Ready,
+            None => ExportStatus::Failed,
+        };
+
+        Ok(diesel::update(data_exports::table.find(eid))
+            .set((
+                data_exports::status.eq(status.as_str()),
+                data_exports::archive.eq(archive),
+            ))
+            .execute(conn)?)
+    }
+
+    pub fn delete_expired(conn: &mut ConnType) -> Result<usize, ApiError> {
+        Ok(diesel::delete(
+            data_exports::table.filter(data_exports::expires_at.lt(diesel::dsl::now)),
+        )
+        .execute(conn)?)
+    }
+}
+
+/// Organization the customer is a member of, as exported
+#[derive(Debug, Serialize)]
+pub struct ExportedMembership {
+    pub org_id: Uuid,
+    pub name: String,
+    pub role: String,
+}
+
+/// Host bound to one of the customer's keys, as exported
+#[derive(Debug, Serialize)]
+pub struct ExportedBinding {
+    pub key_id: i64,
+    pub host_uuid: String,
+}
+
+/// Everything the ssot knows about a customer (GDPR export),
+/// the keys being redacted as they're secrets.
+#[derive(Debug, Serialize)]
+pub struct CustomerExport {
+    pub generated_at: NaiveDateTime,
+    pub profile: Account,
+    pub plan: String,
+    pub organizations: Vec<ExportedMembership>,
+    pub keys: Vec<OrgApiKey>,
+    pub host_bindings: Vec<ExportedBinding>,
+    pub sessions: Vec<CustomerSession>,
+    pub audit_events: Vec<AuditLog>,
+}
+
+impl CustomerExport {
+    pub fn collect(conn: &mut ConnType, cid: &Uuid) -> Result<Self, ApiError> {
+        let profile = Account::get(conn, cid)?;
+        let keys: Vec<OrgApiKey> = OrgApiKey::get_by_customer(conn, cid)?
+            .into_iter()
+            .map(OrgApiKey::redacted)
+            .collect();
+        let host_bindings = keys
+            .iter()
+            .filter_map(|key| {
+                key.host_uuid.as_ref().map(|host_uuid| ExportedBinding {
+                    key_id: key.id,
+                    host_uuid: host_uuid.to_owned(),
+                })
+            })
+            .collect();
+        let organizations = OrgMember::get_by_customer(conn, cid)?
+            .into_iter()
+            .map(|(org_id, name, role)| ExportedMembership { org_id, name, role })
+            .collect();
+
+        Ok(Self {
+            generated_at: Utc::now().naive_utc(),
+            plan: profile.plan.to_owned(),
+            profile,
+            organizations,
+            keys,
+            host_bindings,
+            sessions: CustomerSession::get_by_customer(conn, cid)?,
+            audit_events: AuditLog::get_all_by_customer(conn, cid)?,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, ApiError> {
+        serde_json::to_string_pretty(self).map_err(|err| {
+            error!("Could not serialize the export: {}", err);
+            ApiError::ServerError(None)
+        })
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::now,
+    prelude::*,
+    sql_types::{Int4, Int8, Nullable, Text},
+};
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+
+use crate::{xschema::invite_codes, ConnType};
+
+/// Code allowing to sign up when the registration is invite-only,
+/// usable max_uses times until it expires (if ever).
+#[derive(Queryable, QueryableByName, Debug, Serialize, Deserialize)]
+#[diesel(table_name = invite_codes)]
+pub struct InviteCode {
+    pub code: String,
+    pub created_by: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    pub max_uses: i32,
+    pub uses: i32,
+}
+
+#[derive(Debug)]
+pub struct InviteCodeDTO<'a> {
+    pub code: &'a str,
+    pub created_by: &'a str,
+    /// The code never expires without it
+    pub validity_hours: Option<i64>,
+    pub max_uses: i32,
+}
+
+impl InviteCode {
+    pub fn get_all(conn: &mut ConnType) -> Result<Vec<Self>, ApiError> {
+        Ok(invite_codes::table
+            .order(invite_codes::created_at.desc())
+            .load(conn)?)
+    }
+
+    /// Create the code, its expiry being computed by the database
+    /// which is the clock redeem compares it against.
+    pub fn insert_and_get(conn: &mut ConnType, entry: &InviteCodeDTO) -> Result<Self, ApiError> {
+        Ok(diesel::sql_query(
+            "INSERT INTO invite_codes (code, created_by, expires_at, max_uses)
+            VALUES ($1, $2, NOW() + $3 * INTERVAL '1 hour', $4)
+            RETURNING *",
+        )
+        .bind::<Text, _>(entry.code)
+        .bind::<Text, _>(entry.created_by)
+        .bind::<Nullable<Int8>, _>(entry.validity_hours)
+        .bind::<Int4, _>(entry.max_uses)
+        .get_result(conn)?)
+    }
+
+    pub fn delete(conn: &mut ConnType, code: &str) -> Result<usize, ApiError> {
+        Ok(diesel::delete(invite_codes::table.find(code)).execute(conn)?)
+    }
+
+    /// Use the code once, fail with invite_code_invalid if it
+    /// doesn't exist, is expired or has no use left.
+    pub fn redeem(conn: &mut ConnType, code: &str) -> Result<(), ApiError> {
+        let updated = diesel::update(
+            invite_codes::table.filter(
+                invite_codes::code
+                    .eq(code)
+                    .and(invite_codes::uses.lt(invite_codes::max_uses))
+                    .and(
+                        invite_codes::expires_at
+                            .is_null()
+                            .or(invite_codes::expires_at.gt(now)),
+                    ),
+            ),
+        )
+        .set(invite_codes::uses.eq(invite_codes::uses + 1))
+        .execute(conn)?;
+
+        match updated {
+            0 => Err(ApiError::InvalidRequestError(Some(
+                "invite_code_invalid".to_owned(),
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
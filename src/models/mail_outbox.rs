@@ -0,0 +1,226 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::{now, IntervalDsl},
+    prelude::*,
+    sql_types::{Int4, Int8, Nullable, Text},
+};
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use crate::{xschema::mail_outbox, ConnType, CONFIG};
+
+/// Mail waiting to be (or already) delivered by the mail queue worker.
+/// The bodies are never serialized as they contain the magic links,
+/// and are removed once the mail is sent or dead-lettered.
+#[derive(Queryable, QueryableByName, Debug, Serialize, Deserialize)]
+#[diesel(table_name = mail_outbox)]
+pub struct QueuedMail {
+    pub id: Uuid,
+    pub recipient: String,
+    pub subject: String,
+    #[serde(skip_serializing)]
+    pub plain: Option<String>,
+    #[serde(skip_serializing)]
+    pub html: Option<String>,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub sent_at: Option<NaiveDateTime>,
+    /// The mail is dead-lettered instead of sent past this date (its link expired)
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug)]
+pub struct QueuedMailDTO<'a> {
+    pub recipient: &'a str,
+    pub subject: &'a str,
+    pub plain: &'a str,
+    pub html: Option<&'a str>,
+    /// Lifetime of the link of the mail (in seconds), if any
+    pub expires_in_secs: Option<i64>,
+}
+
+/// Error of the mails dead-lettered as their link expired before delivery
+const EXPIRED_ERROR: &str = "expired before delivery";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailStatus {
+    Pending,
+    Sent,
+    /// Gave up after max_attempts, waiting for an admin
+    Dead,
+}
+
+impl MailStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailStatus::Pending => "pending",
+            MailStatus::Sent => "sent",
+            MailStatus::Dead => "dead",
+        }
+    }
+}
+
+impl QueuedMail {
+    /// Queue the mail, its expiry being computed by the database
+    /// as for every other date of the queue.
+    pub fn enqueue(conn: &mut ConnType, entry: &QueuedMailDTO) -> Result<usize, ApiError> {
+        Ok(diesel::sql_query(
+            "INSERT INTO mail_outbox (recipient, subject, plain, html, expires_at)
+            VALUES ($1, $2, $3, $4, NOW() + $5 * INTERVAL '1 second')",
+        )
+        .bind::<Text, _>(entry.recipient)
+        .bind::<Text, _>(entry.subject)
+        .bind::<Text, _>(entry.plain)
+        .bind::<Nullable<Text>, _>(entry.html)
+        .bind::<Nullable<Int8>, _>(entry.expires_in_secs)
+        .execute(conn)?)
+    }
+
+    /// Dead-letter the pending mails whose link expired, removing their bodies
+    pub fn expire_due(conn: &mut ConnType) -> Result<usize, ApiError> {
+        Ok(diesel::update(
+            mail_outbox::table.filter(
+                mail_outbox::status
+                    .eq(MailStatus::Pending.as_str())
+                    .and(mail_outbox::expires_at.le(now.nullable())),
+            ),
+        )
+        .set((
+            mail_outbox::status.eq(MailStatus::Dead.as_str()),
+            mail_outbox::plain.eq(None::<String>),
+            mail_outbox::html.eq(None::<String>),
+            mail_outbox::last_error.eq(EXPIRED_ERROR),
+        ))
+        .execute(conn)?)
+    }
+
+    /// Take up to batch mails due for delivery, counting the attempt and
+    /// postponing them by lease_secs so that another worker (or instance)
+    /// won't take them, nor lose them if this one dies in between.
+    pub fn claim_due(
+        conn: &mut ConnType,
+        batch: i64,
+        lease_secs: i32,
+    ) -> Result<Vec<Self>, ApiError> {
+        Ok(diesel::sql_query(
+            "UPDATE mail_outbox SET attempts = attempts + 1,
+                next_attempt_at = NOW() + $2 * INTERVAL '1 second'
+            WHERE id IN (
+                SELECT id FROM mail_outbox
+                WHERE status = 'pending' AND next_attempt_at <= NOW()
+                    AND (expires_at IS NULL OR expires_at > NOW())
+                ORDER BY next_attempt_at LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *",
+        )
+        .bind::<Int8, _>(batch)
+        .bind::<Int4, _>(lease_secs)
+        .load(conn)?)
+    }
+
+    /// Mark the mail as sent, removing its bodies (only the metadata are kept)
+    pub fn mark_sent(conn: &mut ConnType, mid: &Uuid) -> Result<usize, ApiError> {
+        Ok(diesel::update(mail_outbox::table.find(mid))
+            .set((
+                mail_outbox::status.eq(MailStatus::Sent.as_str()),
+                mail_outbox::sent_at.eq(now),
+                mail_outbox::plain.eq(None::<String>),
+                mail_outbox::html.eq(None::<String>),
+                mail_outbox::last_error.eq(None::<String>),
+            ))
+            .execute(conn)?)
+    }
+
+    /// Schedule the next attempt with an exponential backoff, or dead-letter
+    /// the mail once it ran out of attempts, removing its bodies.
+    pub fn mark_failed(&self, conn: &mut ConnType, error: &str) -> Result<usize, ApiError> {
+        let queue = &CONFIG.mail_queue;
+        let target = mail_outbox::table.find(self.id);
+        if self.attempts >= queue.max_attempts {
+            return Ok(diesel::update(target)
+                .set((
+                    mail_outbox::status.eq(MailStatus::Dead.as_str()),
+                    mail_outbox::plain.eq(None::<String>),
+                    mail_outbox::html.eq(None::<String>),
+                    mail_outbox::last_error.eq(error),
+                ))
+                .execute(conn)?);
+        }
+        let exponent = (self.attempts - 1).clamp(0, 20) as u32;
+        let backoff = (queue.backoff_base_secs * 2i64.pow(exponent)).min(queue.backoff_max_secs);
+
+        Ok(diesel::update(target)
+            .set((
+                mail_outbox::next_attempt_at.eq(now + (backoff as i32).seconds()),
+                mail_outbox::last_error.eq(error),
+            ))
+            .execute(conn)?)
+    }
+
+    /// Mails dead-lettered or which already failed at least once
+    pub fn get_stuck(conn: &mut ConnType, size: i64, page: i64) -> Result<Vec<Self>, ApiError> {
+        Ok(mail_outbox::table
+            .filter(
+                mail_outbox::status
+                    .eq(MailStatus::Dead.as_str())
+                    .or(mail_outbox::status
+                        .eq(MailStatus::Pending.as_str())
+                        .and(mail_outbox::last_error.is_not_null())),
+            )
+            .order(mail_outbox::created_at.desc())
+            .limit(size)
+            .offset(page * size)
+            .load(conn)?)
+    }
+
+    /// Put a mail not yet sent back in the queue, with all its attempts,
+    /// unless it's dead-lettered or its link expired (the customer has
+    /// to request a new one).
+    pub fn retry(conn: &mut ConnType, mid: &Uuid) -> Result<usize, ApiError> {
+        Ok(diesel::update(
+            mail_outbox::table.filter(
+                mail_outbox::id
+                    .eq(mid)
+                    .and(mail_outbox::status.ne(MailStatus::Sent.as_str()))
+                    .and(mail_outbox::plain.is_not_null())
+                    .and(
+                        mail_outbox::expires_at
+                            .is_null()
+                            .or(mail_outbox::expires_at.gt(now.nullable())),
+                    ),
+            ),
+        )
+        .set((
+            mail_outbox::status.eq(MailStatus::Pending.as_str()),
+            mail_outbox::attempts.eq(0),
+            mail_outbox::next_attempt_at.eq(now),
+        ))
+        .execute(conn)?)
+    }
+
+    pub fn delete(conn: &mut ConnType, mid: &Uuid) -> Result<usize, ApiError> {
+        Ok(diesel::delete(mail_outbox::table.find(mid)).execute(conn)?)
+    }
+
+    /// Remove the mails sent, or queued and dead-lettered, more than retention_days ago
+    pub fn delete_done(conn: &mut ConnType, retention_days: i32) -> Result<usize, ApiError> {
+        let cutoff = now - retention_days.days();
+        Ok(diesel::delete(
+            mail_outbox::table.filter(
+                mail_outbox::status
+                    .eq(MailStatus::Sent.as_str())
+                    .and(mail_outbox::sent_at.lt(cutoff.nullable()))
+                    .or(mail_outbox::status
+                        .eq(MailStatus::Dead.as_str())
+                        .and(mail_outbox::created_at.lt(cutoff))),
+            ),
+        )
+        .execute(conn)?)
+    }
+}
@@ -0,0 +1,27 @@
+mod accounts;
+mod apikeys;
+mod audit_logs;
+mod bertas;
+mod email_changes;
+mod exports;
+mod invite_codes;
+mod mail_outbox;
+mod org_roles;
+mod organizations;
+mod rate_limits;
+mod revoke_links;
+mod sessions;
+
+pub use accounts::*;
+pub use apikeys::*;
+pub use audit_logs::*;
+pub use bertas::*;
+pub use email_changes::*;
+pub use exports::*;
+pub use invite_codes::*;
+pub use mail_outbox::*;
+pub use org_roles::*;
+pub use organizations::*;
+pub use rate_limits::*;
+pub use revoke_links::*;
+pub use sessions::*;
@@ -0,0 +1,101 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+
+/// Role of a customer within an organization
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrgRole {
+    Owner,
+    Admin,
+    Member,
+    ReadOnly,
+}
+
+/// Actions within an organization that are subject to a role check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    KeyRead,
+    KeyCreate,
+    KeyDelete,
+    KeyBind,
+    MemberList,
+    MemberInvite,
+    MemberManage,
+}
+
+impl OrgRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrgRole::Owner => "owner",
+            OrgRole::Admin => "admin",
+            OrgRole::Member => "member",
+            OrgRole::ReadOnly => "readonly",
+        }
+    }
+
+    /// Does the role grant the permission
+    pub fn can(&self, permission: Permission) -> bool {
+        match self {
+            OrgRole::Owner | OrgRole::Admin => true,
+            OrgRole::Member => matches!(
+                permission,
+                Permission::KeyRead
+                    | Permission::KeyCreate
+                    | Permission::KeyBind
+                    | Permission::MemberList
+            ),
+            OrgRole::ReadOnly => {
+                matches!(permission, Permission::KeyRead | Permission::MemberList)
+            }
+        }
+    }
+
+    /// Can a customer with this role give/remove the target role
+    /// to/from someone. Only the owners can manage the other owners.
+    pub fn can_manage(&self, target: OrgRole) -> bool {
+        match self {
+            OrgRole::Owner => true,
+            OrgRole::Admin => target != OrgRole::Owner,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for OrgRole {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(OrgRole::Owner),
+            "admin" => Ok(OrgRole::Admin),
+            "member" => Ok(OrgRole::Member),
+            "readonly" => Ok(OrgRole::ReadOnly),
+            _ => {
+                error!("OrgRole: unknown role {}", s);
+                Err(ApiError::ServerError(None))
+            }
+        }
+    }
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::KeyRead => "key.read",
+            Permission::KeyCreate => "key.create",
+            Permission::KeyDelete => "key.delete",
+            Permission::KeyBind => "key.bind",
+            Permission::MemberList => "member.list",
+            Permission::MemberInvite => "member.invite",
+            Permission::MemberManage => "member.manage",
+        }
+    }
+
+    /// Error returned when the permission is not granted, distinct
+    /// from the SessionError returned when the user is not logged.
+    pub fn denied(&self) -> ApiError {
+        ApiError::AuthorizationError(Some(format!("permission denied: {}", self.as_str())))
+    }
+}
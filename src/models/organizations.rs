@@ -0,0 +1,421 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::{now, IntervalDsl},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use super::{OrgRole, Permission};
+use crate::{
+    xschema::{customers, org_invitations, org_members, organizations},
+    ConnType,
+};
+
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = organizations)]
+pub struct OrganizationDTO<'a> {
+    // None let Postgres generate the id, the personal
+    // organizations reuse the id of their customer.
+    pub id: Option<Uuid>,
+    pub name: &'a str,
+}
+
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct OrgMember {
+    pub org_id: Uuid,
+    pub customer_id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub role: String,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = org_members)]
+pub struct OrgMemberDTO {
+    pub org_id: Uuid,
+    pub customer_id: Uuid,
+    pub role: &'static str,
+}
+
+/// Member of an organization along with his email
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct OrgMemberView {
+    pub customer_id: Uuid,
+    pub email: String,
+    pub role: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct OrgInvitation {
+    pub id: Uuid,
+    pub org_id: Uuid,
+    pub email: String,
+    pub invited_by: Uuid,
+    pub created_at: NaiveDateTime,
+    pub role: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = org_invitations)]
+pub struct OrgInvitationDTO<'a> {
+    pub org_id: Uuid,
+    pub email: &'a str,
+    pub invited_by: Uuid,
+    pub role: &'static str,
+}
+
+impl Organization {
+    pub fn get(conn: &mut ConnType, oid: &Uuid) -> Result<Self, ApiError> {
+        Ok(organizations::table.find(oid).first(conn)?)
+    }
+
+    /// Get all the organizations the customer is a member of
+    pub fn get_by_member(conn: &mut ConnType, cid: &Uuid) -> Result<Vec<Self>, ApiError> {
+        Ok(organizations::table
+            .filter(
+                organizations::id.eq_any(
+                    org_members::table
+                        .filter(org_members::customer_id.eq(cid))
+                        .select(org_members::org_id),
+                ),
+            )
+            .order(organizations::created_at.asc())
+            .load(conn)?)
+    }
+
+    /// Delete the organizations, their members, invitations
+    /// and keys being removed along (on delete cascade).
+    pub fn delete_many(conn: &mut ConnType, oids: &[Uuid]) -> Result<usize, ApiError> {
+        Ok(
+            diesel::delete(organizations::table.filter(organizations::id.eq_any(oids)))
+                .execute(conn)?,
+        )
+    }
+
+    /// Create the organization and add the customer as its owner
+    pub fn create_with_member(
+        conn: &mut ConnType,
+        entry: &OrganizationDTO,
+        cid: &Uuid,
+    ) -> Result<Self, ApiError> {
+        conn.transaction(|conn| {
+            let org: Self = diesel::insert_into(organizations::table)
+                .values(entry)
+                .get_result(conn)?;
+
+            OrgMember::insert(
+                conn,
+                &OrgMemberDTO {
+                    org_id: org.id,
+                    customer_id: *cid,
+                    role: OrgRole::Owner.as_str(),
+                },
+            )?;
+
+            Ok(org)
+        })
+    }
+}
+
+impl OrgMember {
+    pub fn insert(conn: &mut ConnType, entry: &OrgMemberDTO) -> Result<usize, ApiError> {
+        Ok(diesel::insert_into(org_members::table)
+            .values(entry)
+            .on_conflict_do_nothing()
+            .execute(conn)?)
+    }
+
+    /// Get the role of the customer in the organization, if he's a member
+    pub fn get_role(
+        conn: &mut ConnType,
+        oid: &Uuid,
+        cid: &Uuid,
+    ) -> Result<Option<OrgRole>, ApiError> {
+        let role: Option<String> = org_members::table
+            .find((oid, cid))
+            .select(org_members::role)
+            .first(conn)
+            .optional()?;
+
+        role.map(|role| role.parse()).transpose()
+    }
+
+    /// Return the role of the customer if it grant the permission,
+    /// otherwise an AuthorizationError describing the missing permission.
+    pub fn authorize(
+        conn: &mut ConnType,
+        oid: &Uuid,
+        cid: &Uuid,
+        permission: Permission,
+    ) -> Result<OrgRole, ApiError> {
+        match Self::get_role(conn, oid, cid)? {
+            Some(role) if role.can(permission) => Ok(role),
+            _ => Err(permission.denied()),
+        }
+    }
+
+    /// Return an AuthorizationError if the customer is not a member of the organization
+    pub fn ensure(conn: &mut ConnType, oid: &Uuid, cid: &Uuid) -> Result<(), ApiError> {
+        match Self::get_role(conn, oid, cid)? {
+            Some(_) => Ok(()),
+            None => Err(ApiError::AuthorizationError(None)),
+        }
+    }
+
+    pub fn count_owners(conn: &mut ConnType, oid: &Uuid) -> Result<i64, ApiError> {
+        Ok(org_members::table
+            .filter(
+                org_members::org_id
+                    .eq(oid)
+                    .and(org_members::role.eq(OrgRole::Owner.as_str())),
+            )
+            .count()
+            .get_result(conn)?)
+    }
+
+    /// Lock the owners of the organization until the end of the transaction and
+    /// return how many they are, so that two concurrent demotions (or removals)
+    /// can't both see another owner and leave the organization without any.
+    pub fn lock_owners(conn: &mut ConnType, oid: &Uuid) -> Result<usize, ApiError> {
+        let owners: Vec<Uuid> = org_members::table
+            .filter(
+                org_members::org_id
+                    .eq(oid)
+                    .and(org_members::role.eq(OrgRole::Owner.as_str())),
+            )
+            .select(org_members::customer_id)
+            .for_update()
+            .load(conn)?;
+
+        Ok(owners.len())
+    }
+
+    /// Get the ids of the owners of the organization, oldest first
+    pub fn get_owners(conn: &mut ConnType, oid: &Uuid) -> Result<Vec<Uuid>, ApiError> {
+        Ok(org_members::table
+            .filter(
+                org_members::org_id
+                    .eq(oid)
+                    .and(org_members::role.eq(OrgRole::Owner.as_str())),
+            )
+            .select(org_members::customer_id)
+            .order(org_members::created_at.asc())
+            .load(conn)?)
+    }
+
+    /// Make the oldest admin of the organization (or its oldest member, then
+    /// read-only member) an owner, for it not to be left without any when the
+    /// customer (cid) leaves. Return the promoted member, if any.
+    pub fn promote_successor(
+        conn: &mut ConnType,
+        oid: &Uuid,
+        cid: &Uuid,
+    ) -> Result<Option<Uuid>, ApiError> {
+        let others: Vec<(Uuid, String)> = org_members::table
+            .filter(
+                org_members::org_id
+                    .eq(oid)
+                    .and(org_members::customer_id.ne(cid)),
+            )
+            .select((org_members::customer_id, org_members::role))
+            .order(org_members::created_at.asc())
+            .load(conn)?;
+
+        let successor = [OrgRole::Admin, OrgRole::Member, OrgRole::ReadOnly]
+            .iter()
+            .find_map(|rank| others.iter().find(|(_, role)| role == rank.as_str()))
+            .map(|(successor, _)| *successor);
+        if let Some(successor) = &successor {
+            Self::update_role(conn, oid, successor, OrgRole::Owner)?;
+        }
+
+        Ok(successor)
+    }
+
+    pub fn update_role(
+        conn: &mut ConnType,
+        oid: &Uuid,
+        cid: &Uuid,
+        role: OrgRole,
+    ) -> Result<usize, ApiError> {
+        Ok(diesel::update(org_members::table.find((oid, cid)))
+            .set(org_members::role.eq(role.as_str()))
+            .execute(conn)?)
+    }
+
+    pub fn get_by_org(conn: &mut ConnType, oid: &Uuid) -> Result<Vec<OrgMemberView>, ApiError> {
+        Ok(org_members::table
+            .inner_join(customers::table)
+            .filter(org_members::org_id.eq(oid))
+            .select((
+                org_members::customer_id,
+                customers::email,
+                org_members::role,
+                org_members::created_at,
+            ))
+            .order(org_members::created_at.asc())
+            .load(conn)?)
+    }
+
+    pub fn delete(conn: &mut ConnType, oid: &Uuid, cid: &Uuid) -> Result<usize, ApiError> {
+        Ok(diesel::delete(org_members::table.find((oid, cid))).execute(conn)?)
+    }
+
+    /// Get the (organization id, organization name, role) of each membership of the customer
+    pub fn get_by_customer(
+        conn: &mut ConnType,
+        cid: &Uuid,
+    ) -> Result<Vec<(Uuid, String, String)>, ApiError> {
+        Ok(org_members::table
+            .inner_join(organizations::table)
+            .filter(org_members::customer_id.eq(cid))
+            .select((org_members::org_id, organizations::name, org_members::role))
+            .order(org_members::created_at.asc())
+            .load(conn)?)
+    }
+
+    /// Get the organizations of which the customer is the only member
+    /// (his personal organization being one of them).
+    pub fn get_sole_member_orgs(conn: &mut ConnType, cid: &Uuid) -> Result<Vec<Uuid>, ApiError> {
+        let oids: Vec<Uuid> = org_members::table
+            .filter(org_members::customer_id.eq(cid))
+            .select(org_members::org_id)
+            .load(conn)?;
+
+        let mut sole = Vec::new();
+        for oid in oids {
+            let members: i64 = org_members::table
+                .filter(org_members::org_id.eq(oid))
+                .count()
+                .get_result(conn)?;
+            if members == 1 {
+                sole.push(oid);
+            }
+        }
+
+        Ok(sole)
+    }
+
+    /// Get the organizations shared with other members which would be
+    /// left without owner if the customer was removed from them.
+    pub fn get_last_owned_orgs(conn: &mut ConnType, cid: &Uuid) -> Result<Vec<Uuid>, ApiError> {
+        let sole = Self::get_sole_member_orgs(conn, cid)?;
+        let owned: Vec<Uuid> = org_members::table
+            .filter(
+                org_members::customer_id
+                    .eq(cid)
+                    .and(org_members::role.eq(OrgRole::Owner.as_str())),
+            )
+            .select(org_members::org_id)
+            .load(conn)?;
+
+        let mut last = Vec::new();
+        for oid in owned.into_iter().filter(|oid| !sole.contains(oid)) {
+            if Self::count_owners(conn, &oid)? == 1 {
+                last.push(oid);
+            }
+        }
+
+        Ok(last)
+    }
+}
+
+impl OrgInvitation {
+    /// Get the invitation (iid) if it's neither revoked nor expired
+    pub fn get_pending(conn: &mut ConnType, iid: &Uuid) -> Result<Option<Self>, ApiError> {
+        Ok(org_invitations::table
+            .find(iid)
+            .filter(org_invitations::expires_at.gt(now))
+            .first(conn)
+            .optional()?)
+    }
+
+    /// Get the invitations of the organization which are not expired
+    pub fn get_pending_by_org(conn: &mut ConnType, oid: &Uuid) -> Result<Vec<Self>, ApiError> {
+        Ok(org_invitations::table
+            .filter(
+                org_invitations::org_id
+                    .eq(oid)
+                    .and(org_invitations::expires_at.gt(now)),
+            )
+            .order(org_invitations::created_at.desc())
+            .load(conn)?)
+    }
+
+    /// Create the invitation, or renew the pending one for the same email, valid
+    /// for validity_hours from now as seen by the database (like its expiry checks).
+    pub fn upsert(
+        conn: &mut ConnType,
+        entry: &OrgInvitationDTO,
+        validity_hours: i64,
+    ) -> Result<Self, ApiError> {
+        Ok(diesel::insert_into(org_invitations::table)
+            .values((
+                entry,
+                org_invitations::expires_at.eq(now + validity_hours.hours()),
+            ))
+            .on_conflict((org_invitations::org_id, org_invitations::email))
+            .do_update()
+            .set((
+                org_invitations::invited_by.eq(entry.invited_by),
+                org_invitations::role.eq(entry.role),
+                org_invitations::expires_at.eq(now + validity_hours.hours()),
+            ))
+            .get_result(conn)?)
+    }
+
+    /// Revoke the invitation (iid) of the organization (oid)
+    pub fn delete(conn: &mut ConnType, oid: &Uuid, iid: &Uuid) -> Result<usize, ApiError> {
+        Ok(diesel::delete(
+            org_invitations::table.filter(
+                org_invitations::id
+                    .eq(iid)
+                    .and(org_invitations::org_id.eq(oid)),
+            ),
+        )
+        .execute(conn)?)
+    }
+
+    /// Delete the invitations sent by the customer or to his email
+    pub fn delete_by_customer(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        email: &str,
+    ) -> Result<usize, ApiError> {
+        Ok(diesel::delete(
+            org_invitations::table.filter(
+                org_invitations::invited_by
+                    .eq(cid)
+                    .or(org_invitations::email.eq(email)),
+            ),
+        )
+        .execute(conn)?)
+    }
+
+    /// Turn the invitation into a membership of the customer with the invited role
+    pub fn accept(&self, conn: &mut ConnType, cid: &Uuid) -> Result<(), ApiError> {
+        conn.transaction(|conn| {
+            OrgMember::insert(
+                conn,
+                &OrgMemberDTO {
+                    org_id: self.org_id,
+                    customer_id: *cid,
+                    role: self.role.parse::<OrgRole>()?.as_str(),
+                },
+            )?;
+            diesel::delete(org_invitations::table.find(self.id)).execute(conn)?;
+
+            Ok(())
+        })
+    }
+}
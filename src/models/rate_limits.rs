@@ -0,0 +1,46 @@
+use diesel::{
+    dsl::{now, IntervalDsl},
+    prelude::*,
+    sql_types::{Int4, Text},
+};
+use sproot::apierrors::ApiError;
+
+use crate::{xschema::rate_limits, ConnType};
+
+/// State of the window of a rate limit key after a hit
+#[derive(QueryableByName, Debug)]
+pub struct RateCounter {
+    #[diesel(sql_type = Int4)]
+    pub count: i32,
+    /// Seconds before the window of the key is over
+    #[diesel(sql_type = Int4)]
+    pub retry_after: i32,
+}
+
+impl RateCounter {
+    /// Count a hit for the key, starting a new window if the
+    /// previous one (of window_secs) is over. Atomic across workers.
+    pub fn hit(conn: &mut ConnType, key: &str, window_secs: i32) -> Result<Self, ApiError> {
+        Ok(diesel::sql_query(
+            "INSERT INTO rate_limits AS rl (key, window_start, count) VALUES ($1, NOW(), 1)
+            ON CONFLICT (key) DO UPDATE SET
+                count = CASE WHEN rl.window_start + $2 * INTERVAL '1 second' <= NOW()
+                    THEN 1 ELSE rl.count + 1 END,
+                window_start = CASE WHEN rl.window_start + $2 * INTERVAL '1 second' <= NOW()
+                    THEN NOW() ELSE rl.window_start END
+            RETURNING count, CEIL(EXTRACT(EPOCH FROM
+                rl.window_start + $2 * INTERVAL '1 second' - NOW()))::INT4 AS retry_after",
+        )
+        .bind::<Text, _>(key)
+        .bind::<Int4, _>(window_secs)
+        .get_result(conn)?)
+    }
+
+    /// Remove the keys whose window started more than max_age_secs ago
+    pub fn delete_stale(conn: &mut ConnType, max_age_secs: i32) -> Result<usize, ApiError> {
+        Ok(diesel::delete(
+            rate_limits::table.filter(rate_limits::window_start.lt(now - max_age_secs.seconds())),
+        )
+        .execute(conn)?)
+    }
+}
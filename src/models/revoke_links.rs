@@ -0,0 +1,57 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::{now, IntervalDsl},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use crate::{xschema::revoke_links, ConnType};
+
+/// "This wasn't me" link sent with a security notification, its id
+/// being the subject of the Revoke token so that it's only usable once.
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct RevokeLink {
+    pub id: Uuid,
+    pub customer_id: Uuid,
+    /// Email before the change notified, restored when the link is used
+    pub previous_email: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+impl RevokeLink {
+    /// Create the link of the customer, valid for the given days
+    pub fn create(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        previous_email: Option<&str>,
+        validity_days: i32,
+    ) -> Result<Self, ApiError> {
+        Ok(diesel::insert_into(revoke_links::table)
+            .values((
+                revoke_links::customer_id.eq(cid),
+                revoke_links::previous_email.eq(previous_email),
+                revoke_links::expires_at.eq(now + validity_days.days()),
+            ))
+            .get_result(conn)?)
+    }
+
+    /// Mark the link as used and return it, unless it was already
+    /// used or is expired (or doesn't exist).
+    pub fn consume(conn: &mut ConnType, lid: &Uuid) -> Result<Option<Self>, ApiError> {
+        Ok(diesel::update(
+            revoke_links::table.filter(
+                revoke_links::id
+                    .eq(lid)
+                    .and(revoke_links::used_at.is_null())
+                    .and(revoke_links::expires_at.gt(now)),
+            ),
+        )
+        .set(revoke_links::used_at.eq(now))
+        .get_result(conn)
+        .optional()?)
+    }
+}
@@ -0,0 +1,114 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use crate::{
+    xschema::{customer_sessions, customers},
+    ConnType,
+};
+
+/// Server side record of a customer's (cookie) session,
+/// allowing the session to be revoked before it expires.
+#[derive(Queryable, Debug, Serialize, Deserialize)]
+pub struct CustomerSession {
+    pub id: Uuid,
+    pub customer_id: Uuid,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = customer_sessions)]
+pub struct CustomerSessionDTO<'a> {
+    pub customer_id: Uuid,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+impl CustomerSession {
+    pub fn insert_and_get(
+        conn: &mut ConnType,
+        entry: &CustomerSessionDTO,
+    ) -> Result<Self, ApiError> {
+        Ok(diesel::insert_into(customer_sessions::table)
+            .values(entry)
+            .get_result(conn)?)
+    }
+
+    pub fn get_by_customer(conn: &mut ConnType, cid: &Uuid) -> Result<Vec<Self>, ApiError> {
+        Ok(customer_sessions::table
+            .filter(customer_sessions::customer_id.eq(cid))
+            .order(customer_sessions::created_at.desc())
+            .load(conn)?)
+    }
+
+    /// Has the customer already opened a session from this user agent,
+    /// the very first session of the customer counting as a known device.
+    pub fn is_known_device(
+        conn: &mut ConnType,
+        cid: &Uuid,
+        user_agent: Option<&str>,
+    ) -> Result<bool, ApiError> {
+        let agents: Vec<Option<String>> = customer_sessions::table
+            .filter(customer_sessions::customer_id.eq(cid))
+            .select(customer_sessions::user_agent)
+            .distinct()
+            .load(conn)?;
+
+        Ok(agents.is_empty() || agents.iter().any(|agent| agent.as_deref() == user_agent))
+    }
+
+    /// Get whether the customer (cid) is suspended if his session (sid) is
+    /// still valid, both being checked in a single query on each request.
+    pub fn get_active_suspension(
+        conn: &mut ConnType,
+        sid: &Uuid,
+        cid: &Uuid,
+    ) -> Result<Option<bool>, ApiError> {
+        Ok(customer_sessions::table
+            .inner_join(customers::table)
+            .filter(
+                customer_sessions::id
+                    .eq(sid)
+                    .and(customer_sessions::customer_id.eq(cid))
+                    .and(customer_sessions::revoked_at.is_null()),
+            )
+            .select(customers::suspended_at.is_not_null())
+            .first(conn)
+            .optional()?)
+    }
+
+    pub fn revoke(conn: &mut ConnType, sid: &Uuid) -> Result<usize, ApiError> {
+        Ok(diesel::update(
+            customer_sessions::table
+                .find(sid)
+                .filter(customer_sessions::revoked_at.is_null()),
+        )
+        .set(customer_sessions::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)?)
+    }
+
+    /// Revoke all the sessions of the customer (forced logout)
+    pub fn revoke_all(conn: &mut ConnType, cid: &Uuid) -> Result<usize, ApiError> {
+        Ok(diesel::update(
+            customer_sessions::table.filter(
+                customer_sessions::customer_id
+                    .eq(cid)
+                    .and(customer_sessions::revoked_at.is_null()),
+            ),
+        )
+        .set(customer_sessions::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)?)
+    }
+
+    pub fn delete_all(conn: &mut ConnType, cid: &Uuid) -> Result<usize, ApiError> {
+        Ok(
+            diesel::delete(customer_sessions::table.filter(customer_sessions::customer_id.eq(cid)))
+                .execute(conn)?,
+        )
+    }
+}
@@ -2,7 +2,7 @@ use actix_web::{guard, web};
 use sproot::get_session_middleware;
 
 use crate::{
-    api::{apikey, sso},
+    api::{account, admin, apikey, audit, mail, org, sso},
     CONFIG,
 };
 
@@ -11,6 +11,13 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
     // The /ping is used only to get a status over the server
     cfg.route("/ping", web::get().to(|| async { "zpour" }))
         .route("/ping", web::head().to(|| async { "zpour" }))
+        // Authenticated by the key itself (SPTK), without session
+        .route(
+            "/api/key/introspect",
+            web::get().to(apikey::introspect_apikey),
+        )
+        // Authenticated by the bounce token of the mail provider
+        .route("/api/mail/bounce", web::post().to(mail::handle_bounce))
         .service(
             web::scope("/api")
                 .guard(guard::Patch())
@@ -23,6 +30,7 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
                     "SP-CKS".to_string(),
                     CONFIG.cookie_domain.to_owned(),
                 ))
+                .route("/challenge", web::get().to(sso::handle_challenge))
                 .route("/sso", web::post().to(sso::handle_sso))
                 .route("/rsso", web::post().to(sso::handle_rsso))
                 .route("/csso", web::get().to(sso::handle_csso))
@@ -31,6 +39,93 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
                 .route("/key", web::get().to(apikey::get_apikey))
                 .route("/key/list", web::get().to(apikey::get_apikeys))
                 .route("/key", web::post().to(apikey::post_apikey))
-                .route("/key", web::delete().to(apikey::delete_apikey)),
+                .route("/key", web::delete().to(apikey::delete_apikey))
+                .route("/key/host", web::delete().to(apikey::unbind_apikey))
+                .route("/audit", web::get().to(audit::get_audit_logs))
+                .route("/account/delete", web::post().to(account::request_deletion))
+                .route(
+                    "/account/delete/confirm",
+                    web::post().to(account::confirm_deletion),
+                )
+                .route(
+                    "/account/delete/cancel",
+                    web::post().to(account::cancel_deletion),
+                )
+                .route("/account/export", web::post().to(account::request_export))
+                .route("/account/export", web::get().to(account::download_export))
+                .route(
+                    "/account/email",
+                    web::post().to(account::request_email_change),
+                )
+                .route(
+                    "/account/email/confirm",
+                    web::post().to(account::confirm_email_change),
+                )
+                .route(
+                    "/account/email/cancel",
+                    web::post().to(account::cancel_email_change),
+                )
+                .route("/account/locale", web::post().to(account::set_locale))
+                .route(
+                    "/account/notifications",
+                    web::get().to(account::get_notifications),
+                )
+                .route(
+                    "/account/notifications",
+                    web::post().to(account::set_notifications),
+                )
+                .route(
+                    "/account/deliverability",
+                    web::get().to(account::get_deliverability),
+                )
+                .route(
+                    "/account/deliverability/reset",
+                    web::post().to(account::reset_deliverability),
+                )
+                .route(
+                    "/account/sessions/revoke",
+                    web::post().to(account::revoke_sessions),
+                )
+                .route("/org", web::post().to(org::post_org))
+                .route("/org/list", web::get().to(org::get_orgs))
+                .route("/org/select", web::post().to(org::select_org))
+                .route("/org/members", web::get().to(org::get_members))
+                .route("/org/invite", web::post().to(org::invite_member))
+                .route("/org/invite", web::delete().to(org::revoke_invitation))
+                .route("/org/invitations", web::get().to(org::get_invitations))
+                .route("/org/join", web::get().to(org::get_invitation))
+                .route("/org/join", web::post().to(org::join_org))
+                .route("/org/member", web::delete().to(org::remove_member))
+                .route("/org/role", web::post().to(org::update_role)),
+        )
+        // The /admin routes are authenticated by the admins' credentials
+        // (Basic Authorization) and not by the customers' session.
+        .service(
+            web::scope("/admin")
+                .route("/audit", web::get().to(admin::get_audit_logs))
+                .route("/customers", web::get().to(admin::search_customers))
+                .route("/customer/suspend", web::post().to(admin::suspend_customer))
+                .route(
+                    "/customer/unsuspend",
+                    web::post().to(admin::unsuspend_customer),
+                )
+                .route("/customer/logout", web::post().to(admin::logout_customer))
+                .route("/customer/plan", web::post().to(admin::update_plan))
+                .route(
+                    "/customer/deliverability",
+                    web::post().to(admin::reset_deliverability),
+                )
+                .route("/keys", web::get().to(admin::get_keys))
+                .route("/berta/list", web::get().to(admin::get_bertas))
+                .route("/berta", web::post().to(admin::post_berta))
+                .route("/berta", web::delete().to(admin::delete_berta))
+                .route("/invite-code/list", web::get().to(admin::get_invite_codes))
+                .route("/invite-code", web::post().to(admin::post_invite_code))
+                .route("/invite-code", web::delete().to(admin::delete_invite_code))
+                .route("/mail/stuck", web::get().to(admin::get_stuck_mails))
+                .route("/mail/retry", web::post().to(admin::retry_mail))
+                .route("/mail", web::delete().to(admin::delete_mail))
+                .route("/mail/preview", web::get().to(admin::preview_mail))
+                .route("/mail/preview", web::post().to(admin::send_test_mail)),
         );
 }
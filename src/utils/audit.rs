@@ -0,0 +1,352 @@
+use std::net::IpAddr;
+
+use actix_web::{http::header, web, HttpRequest};
+use sproot::models::AuthPool;
+use uuid::Uuid;
+
+use crate::{
+    models::{AuditLog, AuditLogDTO},
+    CONFIG,
+};
+
+/// Security relevant actions recorded in the audit log
+#[derive(Debug, Clone, Copy)]
+pub enum AuditAction {
+    SsoRequest,
+    SsoLogin,
+    SsoRegister,
+    Logout,
+    ApiKeyCreate,
+    ApiKeyDelete,
+    ApiKeyBind,
+    ApiKeyUnbind,
+    MailBounce,
+    OrgInvite,
+    OrgInviteRevoke,
+    OrgJoin,
+    OrgLeave,
+    OrgRoleChange,
+    AccountDeleteRequest,
+    AccountDeleteConfirm,
+    AccountDeleteCancel,
+    AccountDelete,
+    AccountExport,
+    AccountExportDownload,
+    AccountEmailRequest,
+    AccountEmailChange,
+    AccountEmailCancel,
+    AccountLocaleChange,
+    AccountNotificationsChange,
+    AccountSessionsRevoke,
+    AccountDeliverabilityReset,
+    AdminAuditQuery,
+    AdminCustomerSearch,
+    AdminCustomerSuspend,
+    AdminCustomerUnsuspend,
+    AdminForceLogout,
+    AdminPlanChange,
+    AdminDeliverabilityReset,
+    AdminKeyList,
+    AdminBertaList,
+    AdminBertaUpdate,
+    AdminBertaDelete,
+    AdminInviteCodeList,
+    AdminInviteCodeCreate,
+    AdminInviteCodeDelete,
+    AdminMailList,
+    AdminMailRetry,
+    AdminMailDelete,
+    AdminMailPreview,
+    AdminMailTest,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::SsoRequest => "sso.request",
+            AuditAction::SsoRegister => "sso.register",
+            AuditAction::SsoLogin => "sso.login",
+            AuditAction::Logout => "session.logout",
+            AuditAction::ApiKeyCreate => "apikey.create",
+            AuditAction::ApiKeyDelete => "apikey.delete",
+            AuditAction::ApiKeyBind => "apikey.bind",
+            AuditAction::ApiKeyUnbind => "apikey.unbind",
+            AuditAction::MailBounce => "mail.bounce",
+            AuditAction::OrgInvite => "org.invite",
+            AuditAction::OrgInviteRevoke => "org.invite.revoke",
+            AuditAction::OrgJoin => "org.join",
+            AuditAction::OrgLeave => "org.leave",
+            AuditAction::OrgRoleChange => "org.role",
+            AuditAction::AccountDeleteRequest => "account.delete.request",
+            AuditAction::AccountDeleteConfirm => "account.delete.confirm",
+            AuditAction::AccountDeleteCancel => "account.delete.cancel",
+            AuditAction::AccountDelete => "account.delete",
+            AuditAction::AccountExport => "account.export",
+            AuditAction::AccountExportDownload => "account.export.download",
+            AuditAction::AccountEmailRequest => "account.email.request",
+            AuditAction::AccountEmailChange => "account.email.change",
+            AuditAction::AccountEmailCancel => "account.email.cancel",
+            AuditAction::AccountLocaleChange => "account.locale",
+            AuditAction::AccountNotificationsChange => "account.notifications",
+            AuditAction::AccountSessionsRevoke => "account.sessions.revoke",
+            AuditAction::AccountDeliverabilityReset => "account.deliverability.reset",
+            AuditAction::AdminAuditQuery => "admin.audit.query",
+            AuditAction::AdminCustomerSearch => "admin.customer.search",
+            AuditAction::AdminCustomerSuspend => "admin.customer.suspend",
+            AuditAction::AdminCustomerUnsuspend => "admin.customer.unsuspend",
+            AuditAction::AdminForceLogout => "admin.customer.logout",
+            AuditAction::AdminPlanChange => "admin.customer.plan",
+            AuditAction::AdminDeliverabilityReset => "admin.customer.deliverability",
+            AuditAction::AdminKeyList => "admin.key.list",
+            AuditAction::AdminBertaList => "admin.berta.list",
+            AuditAction::AdminBertaUpdate => "admin.berta.update",
+            AuditAction::AdminBertaDelete => "admin.berta.delete",
+            AuditAction::AdminInviteCodeList => "admin.invite_code.list",
+            AuditAction::AdminInviteCodeCreate => "admin.invite_code.create",
+            AuditAction::AdminInviteCodeDelete => "admin.invite_code.delete",
+            AuditAction::AdminMailList => "admin.mail.list",
+            AuditAction::AdminMailRetry => "admin.mail.retry",
+            AuditAction::AdminMailDelete => "admin.mail.delete",
+            AuditAction::AdminMailPreview => "admin.mail.preview",
+            AuditAction::AdminMailTest => "admin.mail.test",
+        }
+    }
+}
+
+/// Origin of a request (IP and user agent) as stored in the audit log
+#[derive(Debug, Clone, Default)]
+pub struct RequestMeta {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl RequestMeta {
+    pub fn from_request(request: &HttpRequest) -> Self {
+        Self {
+            ip: client_ip(request).map(|ip| ip.to_string()),
+            user_agent: request
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|ua| ua.to_str().ok())
+                .map(str::to_owned),
+        }
+    }
+}
+
+/// Ip of the client: the direct peer, unless it is one of the trusted_proxies
+/// in which case the forwarded chain (Forwarded or X-Forwarded-For) is walked
+/// from the closest hop, the first address not being a trusted proxy being the
+/// client's. The hops added before it can be forged by the client and are ignored.
+fn client_ip(request: &HttpRequest) -> Option<IpAddr> {
+    client_ip_with(request, &CONFIG.trusted_proxies)
+}
+
+/// Ip of the client as client_ip finds it, behind the given trusted proxies
+fn client_ip_with(request: &HttpRequest, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer = request.peer_addr()?.ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let mut client = peer;
+    for hop in forwarded_chain(request).iter().rev() {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            }
+            // Obfuscated or unknown hop, the proxy can't tell who's behind
+            Err(_) => break,
+        }
+    }
+
+    Some(client)
+}
+
+/// Addresses of the forwarded chain, the client's first and the closest hop last
+fn forwarded_chain(request: &HttpRequest) -> Vec<String> {
+    let headers = request.headers();
+    let forwarded: Vec<String> = headers
+        .get_all(header::FORWARDED)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case("for").then(|| strip_port(value))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(strip_port)
+        .collect()
+}
+
+/// Address of a forwarded node, eg: "192.0.2.60", "\"[2001:db8::1]:4711\""
+fn strip_port(node: &str) -> String {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or_default().to_owned();
+    }
+    match node.split_once(':') {
+        // A single colon separates the port of an ipv4
+        Some((ip, port)) if !port.contains(':') => ip.to_owned(),
+        _ => node.to_owned(),
+    }
+}
+
+/// Event waiting to be written to the audit log
+#[derive(Debug)]
+pub struct AuditEvent {
+    action: AuditAction,
+    actor: String,
+    customer_id: Option<Uuid>,
+    target: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, actor: impl Into<String>) -> Self {
+        Self {
+            action,
+            actor: actor.into(),
+            customer_id: None,
+            target: None,
+        }
+    }
+
+    /// Customer whose account is concerned by the event
+    pub fn customer(mut self, customer_id: Uuid) -> Self {
+        self.customer_id = Some(customer_id);
+        self
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Write the event to the audit log.
+    ///
+    /// A failure to write is logged but never returned, so that the
+    /// request which generated the event still complete normally.
+    pub async fn record(self, db: &web::Data<AuthPool>, meta: &RequestMeta, success: bool) {
+        let action = self.action;
+        let entry = AuditLogDTO {
+            customer_id: self.customer_id,
+            actor: self.actor,
+            action: action.as_str().to_owned(),
+            target: self.target,
+            ip: meta.ip.to_owned(),
+            user_agent: meta.user_agent.to_owned(),
+            success,
+        };
+
+        let db = db.clone();
+        match web::block(move || AuditLog::insert(&mut db.pool.get()?, &entry)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => error!("AUDIT: cannot record {}: {}", action.as_str(), err),
+            Err(err) => error!("AUDIT: cannot record {}: {}", action.as_str(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    const PROXY: &str = "10.0.0.1";
+    const EDGE: &str = "10.0.0.2";
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn request(peer: &str, headers: &[(&str, &str)]) -> HttpRequest {
+        let mut request =
+            TestRequest::default().peer_addr(format!("{}:443", peer).parse().unwrap());
+        for header in headers {
+            request = request.insert_header(*header);
+        }
+        request.to_http_request()
+    }
+
+    fn client(request: &HttpRequest) -> Option<String> {
+        client_ip_with(request, &[ip(PROXY), ip(EDGE)]).map(|ip| ip.to_string())
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        // The header of a direct client is ignored
+        let direct = request("203.0.113.7", &[("x-forwarded-for", "198.51.100.1")]);
+        assert_eq!(client(&direct).as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_for() {
+        let proxied = request(PROXY, &[("x-forwarded-for", "198.51.100.1")]);
+        assert_eq!(client(&proxied).as_deref(), Some("198.51.100.1"));
+    }
+
+    #[test]
+    fn forged_hops_are_ignored() {
+        // The client prepended a hop, the first untrusted one from the proxy side is kept
+        let forged = request(
+            PROXY,
+            &[("x-forwarded-for", "192.0.2.66, 198.51.100.1, 10.0.0.2")],
+        );
+        assert_eq!(client(&forged).as_deref(), Some("198.51.100.1"));
+    }
+
+    #[test]
+    fn only_trusted_hops() {
+        let internal = request(PROXY, &[("x-forwarded-for", "10.0.0.2")]);
+        assert_eq!(client(&internal).as_deref(), Some(EDGE));
+    }
+
+    #[test]
+    fn unknown_hop() {
+        // The proxy can't tell who's behind an obfuscated hop
+        let obfuscated = request(PROXY, &[("x-forwarded-for", "198.51.100.1, unknown")]);
+        assert_eq!(client(&obfuscated).as_deref(), Some(PROXY));
+    }
+
+    #[test]
+    fn forwarded_header() {
+        let proxied = request(
+            PROXY,
+            &[
+                (
+                    "forwarded",
+                    "for=192.0.2.43, for=\"[2001:db8:cafe::17]:4711\";proto=https",
+                ),
+                ("x-forwarded-for", "198.51.100.1"),
+            ],
+        );
+        // Forwarded takes precedence over X-Forwarded-For
+        assert_eq!(client(&proxied).as_deref(), Some("2001:db8:cafe::17"));
+    }
+
+    #[test]
+    fn no_peer() {
+        assert_eq!(client(&TestRequest::default().to_http_request()), None);
+    }
+
+    #[test]
+    fn ports_are_stripped() {
+        assert_eq!(strip_port("192.0.2.60:8080"), "192.0.2.60");
+        assert_eq!(strip_port(" 192.0.2.60 "), "192.0.2.60");
+        assert_eq!(strip_port("\"[2001:db8::1]:4711\""), "2001:db8::1");
+        assert_eq!(strip_port("2001:db8::1"), "2001:db8::1");
+    }
+}
@@ -0,0 +1,289 @@
+use serde::Deserialize;
+use sproot::apierrors::ApiError;
+
+use crate::models::Deliverability;
+
+/// Bounce (or complaint) reported for an email by the mail provider,
+/// the email being as reported (it's to be normalized by the caller)
+#[derive(Debug, Deserialize)]
+pub struct BounceEvent {
+    pub email: String,
+    #[serde(rename = "type")]
+    pub state: Deliverability,
+    pub reason: Option<String>,
+}
+
+/// Generic JSON report, a single event or a list of them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BounceReport {
+    One(BounceEvent),
+    Many(Vec<BounceEvent>),
+}
+
+/// Parse the generic JSON report:
+/// `{ "email": "...", "type": "hard-bounce", "reason": "..." }` (or a list),
+/// type being one of soft-bounce, hard-bounce or complaint.
+pub fn parse_json(body: &[u8]) -> Result<Vec<BounceEvent>, ApiError> {
+    let events = match serde_json::from_slice(body) {
+        Ok(BounceReport::One(event)) => vec![event],
+        Ok(BounceReport::Many(events)) => events,
+        Err(err) => {
+            trace!("BOUNCE: invalid json report: {}", err);
+            return Err(ApiError::InvalidRequestError(None));
+        }
+    };
+
+    if events.iter().any(|event| event.state == Deliverability::Ok) {
+        return Err(ApiError::InvalidRequestError(None));
+    }
+
+    Ok(events)
+}
+
+/// Parse a delivery status notification (RFC 3464) or an abuse report
+/// (RFC 5965), either alone or as the whole multipart/report message.
+/// The failed recipients are hard bounces only when the status is explicitly
+/// permanent (5.x.x), the other failures (temporary, missing or malformed
+/// status) and the delays being soft bounces: a single dubious report must
+/// not stop the customer's magic links.
+pub fn parse_report(report: &str) -> Vec<BounceEvent> {
+    let mut events = Vec::new();
+
+    for fields in field_blocks(report) {
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        // Abuse report: the recipient complained about the mail
+        if let Some(feedback) = field("feedback-type") {
+            if let Some(recipient) = field("original-rcpt-to") {
+                push_event(
+                    &mut events,
+                    recipient,
+                    Deliverability::Complaint,
+                    Some(feedback),
+                );
+            }
+            continue;
+        }
+
+        let recipient = match field("final-recipient").or_else(|| field("original-recipient")) {
+            Some(recipient) => recipient,
+            None => continue,
+        };
+        let permanent = field("status").is_some_and(is_permanent_status);
+        let state = match field("action").map(str::to_ascii_lowercase).as_deref() {
+            Some("failed") if permanent => Deliverability::HardBounce,
+            Some("failed") | Some("delayed") => Deliverability::SoftBounce,
+            // delivered, relayed or expanded
+            _ => continue,
+        };
+        let reason = field("diagnostic-code")
+            .map(strip_type)
+            .or_else(|| field("status"));
+        push_event(&mut events, recipient, state, reason);
+    }
+
+    events
+}
+
+fn push_event(
+    events: &mut Vec<BounceEvent>,
+    recipient: &str,
+    state: Deliverability,
+    reason: Option<&str>,
+) {
+    let address = strip_type(recipient)
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    if address.is_empty() {
+        return;
+    }
+    events.push(BounceEvent {
+        email: address.to_owned(),
+        state,
+        reason: reason.map(str::to_owned),
+    });
+}
+
+/// Is the status code (RFC 3463) a permanent failure: 5.x.x, x being digits
+fn is_permanent_status(status: &str) -> bool {
+    // The status may be followed by a comment, eg: "5.1.1 (unknown user)"
+    let code = status.split_whitespace().next().unwrap_or_default();
+    let mut parts = code.split('.');
+    parts.next() == Some("5")
+        && parts.clone().count() == 2
+        && parts.all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Remove the type of the value, eg: "rfc822; a@b.c" or "smtp; 550 ..."
+fn strip_type(value: &str) -> &str {
+    match value.split_once(';') {
+        Some((_, rest)) => rest.trim(),
+        None => value.trim(),
+    }
+}
+
+/// Split the report in blocks of "Name: value" fields separated by blank
+/// lines, the names being lowercased and the folded lines unfolded.
+fn field_blocks(report: &str) -> Vec<Vec<(String, String)>> {
+    let mut blocks = Vec::new();
+    let mut fields: Vec<(String, String)> = Vec::new();
+
+    for line in report.lines() {
+        if line.trim().is_empty() {
+            if !fields.is_empty() {
+                blocks.push(std::mem::take(&mut fields));
+            }
+        } else if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        }
+    }
+    if !fields.is_empty() {
+        blocks.push(fields);
+    }
+
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DSN: &str = "Reporting-MTA: dns; mx.example.com
+Arrival-Date: Mon, 19 Oct 2026 10:00:00 +0000
+
+Final-Recipient: rfc822; <Jane@Example.com>
+Action: failed
+Status: 5.1.1
+Diagnostic-Code: smtp; 550 5.1.1 unknown user
+
+Final-Recipient: rfc822; bob@example.com
+Action: failed
+Status: 4.2.2
+Diagnostic-Code: smtp; 452 4.2.2 mailbox full
+
+Original-Recipient: rfc822; carol@example.com
+Action: delayed
+Status: 4.4.7
+
+Final-Recipient: rfc822; dave@example.com
+Action: delivered
+Status: 2.0.0
+";
+
+    #[test]
+    fn dsn_permanent_failure_is_hard_bounce() {
+        let events = parse_report(DSN);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].email, "Jane@Example.com");
+        assert_eq!(events[0].state, Deliverability::HardBounce);
+        assert_eq!(events[0].reason.as_deref(), Some("550 5.1.1 unknown user"));
+    }
+
+    #[test]
+    fn dsn_temporary_failure_and_delay_are_soft_bounces() {
+        let events = parse_report(DSN);
+        assert_eq!(events[1].email, "bob@example.com");
+        assert_eq!(events[1].state, Deliverability::SoftBounce);
+        assert_eq!(events[2].email, "carol@example.com");
+        assert_eq!(events[2].state, Deliverability::SoftBounce);
+        assert_eq!(events[2].reason.as_deref(), Some("4.4.7"));
+    }
+
+    #[test]
+    fn dsn_missing_or_malformed_status_is_soft_bounce() {
+        let report = "Final-Recipient: rfc822; jane@example.com
+Action: failed
+
+Final-Recipient: rfc822; bob@example.com
+Action: failed
+Status: 5.x
+";
+        let events = parse_report(report);
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .all(|event| event.state == Deliverability::SoftBounce));
+    }
+
+    #[test]
+    fn dsn_folded_fields() {
+        let report = "Final-Recipient: rfc822;
+ jane@example.com
+Action: failed
+Status: 5.2.1 (mailbox
+ disabled)
+";
+        let events = parse_report(report);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].email, "jane@example.com");
+        assert_eq!(events[0].state, Deliverability::HardBounce);
+    }
+
+    #[test]
+    fn arf_complaint() {
+        let report = "Feedback-Type: abuse
+User-Agent: SomeGenerator/1.0
+Version: 1
+Original-Rcpt-To: <jane@example.com>
+";
+        let events = parse_report(report);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].email, "jane@example.com");
+        assert_eq!(events[0].state, Deliverability::Complaint);
+        assert_eq!(events[0].reason.as_deref(), Some("abuse"));
+    }
+
+    #[test]
+    fn arf_without_recipient_is_ignored() {
+        assert!(parse_report("Feedback-Type: abuse\nVersion: 1\n").is_empty());
+    }
+
+    #[test]
+    fn json_single_and_list() {
+        let events = parse_json(br#"{ "email": "jane@example.com", "type": "hard-bounce" }"#)
+            .expect("single event");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, Deliverability::HardBounce);
+        assert!(events[0].reason.is_none());
+
+        let events = parse_json(
+            br#"[
+                { "email": "jane@example.com", "type": "soft-bounce", "reason": "mailbox full" },
+                { "email": "bob@example.com", "type": "complaint" }
+            ]"#,
+        )
+        .expect("list of events");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].reason.as_deref(), Some("mailbox full"));
+        assert_eq!(events[1].state, Deliverability::Complaint);
+    }
+
+    #[test]
+    fn json_rejects_ok_and_unknown_types() {
+        assert!(parse_json(br#"{ "email": "jane@example.com", "type": "ok" }"#).is_err());
+        assert!(parse_json(br#"{ "email": "jane@example.com", "type": "bounce" }"#).is_err());
+        assert!(parse_json(br#"{ "email": "jane@example.com" }"#).is_err());
+    }
+
+    #[test]
+    fn permanent_status() {
+        assert!(is_permanent_status("5.1.1"));
+        assert!(is_permanent_status("5.7.26 (unauthenticated)"));
+        assert!(!is_permanent_status("4.2.2"));
+        assert!(!is_permanent_status("5"));
+        assert!(!is_permanent_status("5.1"));
+        assert!(!is_permanent_status("50.1.1"));
+        assert!(!is_permanent_status(""));
+    }
+}
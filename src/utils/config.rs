@@ -1,8 +1,11 @@
+use std::net::IpAddr;
+
 use clap::Parser;
 use config::ConfigError;
 use lettre::message::Mailbox;
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize};
 
+use super::i18n::Locale;
 use crate::Args;
 
 #[derive(Debug, Deserialize, Clone)]
@@ -26,24 +29,339 @@ pub struct Config {
 
     pub cookie_secret: String,
     pub cookie_domain: Option<String>,
+    /// Reverse proxies whose Forwarded/X-Forwarded-For headers are honored
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
 
     // AUTH SETTINGS
     pub sso_base_url: String,
-    pub jwt_ec_priv: String,
-    pub jwt_ec_pub: String,
+    #[serde(default)]
+    pub email_local_part: LocalPartPolicy,
+    #[serde(default = "default_locale")]
+    pub default_locale: String,
+    /// PEM keys of the jwt.algorithm (named jwt_ec_* when only EC was supported)
+    #[serde(alias = "jwt_ec_priv")]
+    pub jwt_priv: String,
+    #[serde(alias = "jwt_ec_pub")]
+    pub jwt_pub: String,
+    #[serde(default)]
+    pub jwt: JwtConfig,
+    #[serde(default = "default_invite_validity")]
+    pub invite_validity_hours: i64,
+    #[serde(default = "default_deletion_grace")]
+    pub deletion_grace_days: i64,
+    #[serde(default = "default_export_sync_max_events")]
+    pub export_sync_max_events: i64,
+    #[serde(default = "default_export_validity")]
+    pub export_validity_hours: i64,
+
+    // MAIL TEMPLATES SETTINGS
+    pub templates_dir: Option<String>,
+    #[serde(default)]
+    pub branding: Branding,
+
+    // SIGN UP SETTINGS
+    #[serde(default)]
+    pub registration_mode: RegistrationMode,
+    #[serde(default)]
+    pub signup_domains: DomainPolicy,
+
+    // RATE LIMITS SETTINGS
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+
+    // PROOF OF WORK SETTINGS
+    #[serde(default)]
+    pub pow: PowConfig,
+
+    // ADMIN SETTINGS
+    #[serde(default)]
+    pub admins: Vec<AdminCredential>,
+
+    // MAIL QUEUE SETTINGS
+    #[serde(default)]
+    pub mail_queue: MailQueueConfig,
+
+    // BOUNCE SETTINGS
+    pub bounce_token_sha256: Option<String>,
+    /// Hours after a hard bounce before a sign in request retries the email
+    #[serde(default = "default_bounce_retry")]
+    pub bounce_retry_hours: i32,
+
+    // MAIL TRANSPORT SETTINGS
+    #[serde(default)]
+    pub mail_transport: MailTransportConfig,
+
+    // DKIM SETTINGS
+    pub dkim: Option<DkimSettings>,
 
     // SMTP SETTINGS
     #[serde(default = "default_smtp_port")]
     pub smtp_port: u16,
     #[serde(default = "default_smtp_tls")]
     pub smtp_tls: bool,
+    #[serde(default)]
     pub smtp_host: String,
+    #[serde(default)]
     pub smtp_user: String,
+    #[serde(default)]
     pub smtp_password: String,
     #[serde(deserialize_with = "mailbox_deser")]
     pub smtp_email_sender: Mailbox,
 }
 
+/// Claims of the issued tokens and their validation, the tokens
+/// issued with another issuer or audience being refused.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct JwtConfig {
+    pub algorithm: JwtAlgorithm,
+    /// Validity of the magic links (sso tokens)
+    pub magic_link_minutes: i64,
+    pub issuer: String,
+    pub audience: String,
+    /// Clock skew tolerated when checking the expiry and issue time
+    pub leeway_secs: u64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: JwtAlgorithm::default(),
+            magic_link_minutes: 5,
+            issuer: "saas-auth-ssot".to_owned(),
+            audience: "saas-auth-ssot".to_owned(),
+            leeway_secs: 60,
+        }
+    }
+}
+
+/// Algorithm signing the tokens, the keys being EC (P-256 or P-384),
+/// Ed25519 or RSA accordingly.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    #[default]
+    ES256,
+    ES384,
+    EdDSA,
+    RS256,
+    PS256,
+}
+
+/// Credentials of an admin, the token is stored as its sha256 (hex)
+#[derive(Debug, Deserialize, Clone)]
+pub struct AdminCredential {
+    pub name: String,
+    pub token_sha256: String,
+}
+
+/// Branding of the mails, available to every template as `brand`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Branding {
+    pub product_name: String,
+    pub logo_url: String,
+    pub support_label: String,
+    pub support_url: String,
+    /// Color of the buttons (css)
+    pub accent_color: String,
+    /// Text shown at the bottom of the mails
+    pub footer: Option<String>,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            product_name: "Speculare".to_owned(),
+            logo_url: "https://speculare.cloud/assets/imgs/logo_light.png".to_owned(),
+            support_label: "@specularecloud".to_owned(),
+            support_url: "#".to_owned(),
+            accent_color: "#3c83f6".to_owned(),
+            footer: None,
+        }
+    }
+}
+
+/// Domains allowed to sign up: only the allowlist ones if it's not empty,
+/// never the denylist ones nor the disposable ones (if block_disposable).
+/// The subdomains of the listed domains are concerned too. The policy also
+/// applies to the sign in requests and to the email changes.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct DomainPolicy {
+    pub allowlist: Vec<String>,
+    pub denylist: Vec<String>,
+    pub block_disposable: bool,
+    /// File replacing the bundled list of disposable domains
+    pub disposable_list: Option<String>,
+}
+
+impl Default for DomainPolicy {
+    fn default() -> Self {
+        Self {
+            allowlist: Vec::new(),
+            denylist: Vec::new(),
+            block_disposable: true,
+            disposable_list: None,
+        }
+    }
+}
+
+/// Who can create an account through the sso routes or by accepting an
+/// invitation to an organization: the invitation stands for the invite code
+/// in invite-only (the existing customers vouching for the ones they invite),
+/// and only the customers who already have an account can join when closed.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistrationMode {
+    #[default]
+    Open,
+    /// Only with an invite code generated by an admin
+    InviteOnly,
+    Closed,
+}
+
+/// Maximum number of requests allowed per window (in seconds), 0 to disable
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct RateWindow {
+    pub max: i32,
+    pub window_secs: i32,
+}
+
+/// Windows of the rate limits applied to the sensitive routes
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimits {
+    pub sso_per_ip: RateWindow,
+    /// Sign in mails requested for a same email from a same ip
+    pub sso_per_email: RateWindow,
+    /// Sign in mails requested for a same email from any ip, bounding
+    /// what a victim gets even from rotating ips
+    pub sso_per_recipient: RateWindow,
+    pub key_create_per_customer: RateWindow,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            sso_per_ip: RateWindow {
+                max: 30,
+                window_secs: 3600,
+            },
+            sso_per_email: RateWindow {
+                max: 5,
+                window_secs: 3600,
+            },
+            sso_per_recipient: RateWindow {
+                max: 20,
+                window_secs: 3600,
+            },
+            key_create_per_customer: RateWindow {
+                max: 20,
+                window_secs: 3600,
+            },
+        }
+    }
+}
+
+/// Proof of work (hashcash) required to request a sign in mail,
+/// the difficulty (leading zero bits) growing by one every `step`
+/// challenges requested in the window from the same ip or for the same email.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct PowConfig {
+    pub enabled: bool,
+    pub base_difficulty: u32,
+    pub max_difficulty: u32,
+    pub step: i32,
+    pub window_secs: i32,
+}
+
+impl Default for PowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            base_difficulty: 16,
+            max_difficulty: 24,
+            step: 10,
+            window_secs: 600,
+        }
+    }
+}
+
+/// Delivery of the queued mails: every poll_secs up to batch_size mails
+/// are sent, the failed ones being retried after backoff_base_secs
+/// doubled at each attempt (up to backoff_max_secs) until max_attempts.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct MailQueueConfig {
+    pub poll_secs: u64,
+    pub batch_size: i64,
+    pub max_attempts: i32,
+    pub backoff_base_secs: i64,
+    pub backoff_max_secs: i64,
+    /// Days the sent (or dead-lettered) mails are kept before being removed
+    pub retention_days: i32,
+}
+
+impl Default for MailQueueConfig {
+    fn default() -> Self {
+        Self {
+            poll_secs: 5,
+            batch_size: 20,
+            max_attempts: 8,
+            backoff_base_secs: 30,
+            backoff_max_secs: 3600,
+            retention_days: 7,
+        }
+    }
+}
+
+/// How the mails are delivered, the smtp_* settings being only
+/// required (and the startup check fatal) for the SMTP transport.
+#[derive(Debug, Default, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum MailTransportConfig {
+    #[default]
+    Smtp,
+    /// Local sendmail binary, `sendmail` from the PATH by default
+    Sendmail { command: Option<String> },
+    /// Write each mail as an .eml file in the directory
+    File { dir: String },
+    /// Log each mail to the standard output
+    Stdout,
+}
+
+/// DKIM signature of the outgoing mails, the public key being
+/// published in the `<selector>._domainkey.<domain>` TXT record.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DkimSettings {
+    pub selector: String,
+    pub domain: String,
+    /// File of the private key: PKCS#1 PEM for rsa, base64 for ed25519
+    pub private_key: String,
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DkimAlgorithm {
+    #[default]
+    Rsa,
+    Ed25519,
+}
+
+/// How the local part (before the @) of the emails is normalized,
+/// the domain being always lowercased.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalPartPolicy {
+    #[default]
+    Lowercase,
+    Preserve,
+}
+
 impl Config {
     pub fn new() -> Result<Self, ConfigError> {
         let args = Args::parse();
@@ -65,6 +383,23 @@ impl Config {
                 );
                 std::process::exit(1);
             }
+            if matches!(&config.templates_dir, Some(dir) if !std::path::Path::new(dir).is_dir()) {
+                warn!("config: 'templates_dir' is not a directory, using the default templates");
+            }
+            if config.jwt.magic_link_minutes < 1
+                || config.jwt.issuer.is_empty()
+                || config.jwt.audience.is_empty()
+            {
+                error!("error: config: 'jwt' needs a positive 'magic_link_minutes', 'issuer' and 'audience'");
+                std::process::exit(1);
+            }
+            if Locale::from_tag(&config.default_locale).is_none() {
+                error!(
+                    "error: config: 'default_locale' {} is not translated",
+                    config.default_locale
+                );
+                std::process::exit(1);
+            }
         }
 
         config
@@ -92,6 +427,31 @@ fn default_workers() -> usize {
     }
 }
 
+fn default_locale() -> String {
+    "en".to_owned()
+}
+
+fn default_invite_validity() -> i64 {
+    // 7 days
+    168
+}
+
+fn default_deletion_grace() -> i64 {
+    30
+}
+
+fn default_export_sync_max_events() -> i64 {
+    1000
+}
+
+fn default_export_validity() -> i64 {
+    48
+}
+
+fn default_bounce_retry() -> i32 {
+    24
+}
+
 fn default_smtp_port() -> u16 {
     587
 }
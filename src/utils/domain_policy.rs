@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use sproot::apierrors::ApiError;
+
+use crate::CONFIG;
+
+// Bundled list of the disposable email domains
+const BUNDLED_DISPOSABLE: &str = include_str!("../../assets/disposable_domains.txt");
+
+// Lazy static of the disposable domains, read from the disposable_list
+// file if defined (to use an up to date list) or from the bundled one.
+static DISPOSABLE_DOMAINS: Lazy<HashSet<String>> = Lazy::new(|| {
+    let content = match &CONFIG.signup_domains.disposable_list {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => {
+                error!(
+                    "DOMAINS: cannot read {}, using the bundled list: {}",
+                    path, err
+                );
+                BUNDLED_DISPOSABLE.to_owned()
+            }
+        },
+        None => BUNDLED_DISPOSABLE.to_owned(),
+    };
+
+    parse_domains(&content)
+});
+
+// Lazy statics of the allowlist and denylist, lowercased for the lookups
+static ALLOWED_DOMAINS: Lazy<HashSet<String>> =
+    Lazy::new(|| to_domain_set(&CONFIG.signup_domains.allowlist));
+static DENIED_DOMAINS: Lazy<HashSet<String>> =
+    Lazy::new(|| to_domain_set(&CONFIG.signup_domains.denylist));
+
+/// Parse a list of domains, one per line, ignoring the comments (#)
+fn parse_domains(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn to_domain_set(domains: &[String]) -> HashSet<String> {
+    domains
+        .iter()
+        .map(|domain| domain.trim().to_lowercase())
+        .collect()
+}
+
+/// Does the domain (or one of its parent domains) belong to the set
+fn matches(domain: &str, set: &HashSet<String>) -> bool {
+    let domain = domain.to_lowercase();
+    let mut candidate = domain.as_str();
+    loop {
+        if set.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => candidate = parent,
+            _ => return false,
+        }
+    }
+}
+
+/// Check that customers can sign up with an email of the (normalized)
+/// domain, or return the error code explaining why they can't.
+pub fn check_signup_domain(domain: &str) -> Result<(), ApiError> {
+    let disposable = CONFIG
+        .signup_domains
+        .block_disposable
+        .then_some(&*DISPOSABLE_DOMAINS);
+
+    check_domain_with(domain, &ALLOWED_DOMAINS, &DENIED_DOMAINS, disposable)
+}
+
+/// Check the domain as check_signup_domain does, against the given lists
+/// (an empty allowed list allowing every domain, and no disposable one
+/// when they're not blocked).
+fn check_domain_with(
+    domain: &str,
+    allowed: &HashSet<String>,
+    denied: &HashSet<String>,
+    disposable: Option<&HashSet<String>>,
+) -> Result<(), ApiError> {
+    if !allowed.is_empty() && !matches(domain, allowed) {
+        return Err(ApiError::InvalidRequestError(Some(
+            "domain_not_allowed".to_owned(),
+        )));
+    }
+    if matches(domain, denied) {
+        return Err(ApiError::InvalidRequestError(Some(
+            "domain_denied".to_owned(),
+        )));
+    }
+    if matches!(disposable, Some(disposable) if matches(domain, disposable)) {
+        return Err(ApiError::InvalidRequestError(Some(
+            "disposable_email".to_owned(),
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(domains: &[&str]) -> HashSet<String> {
+        to_domain_set(
+            &domains
+                .iter()
+                .map(|domain| domain.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Error code of the check, None if the domain is accepted
+    fn refusal(
+        domain: &str,
+        allowed: &HashSet<String>,
+        denied: &HashSet<String>,
+        disposable: Option<&HashSet<String>>,
+    ) -> Option<String> {
+        match check_domain_with(domain, allowed, denied, disposable) {
+            Ok(()) => None,
+            Err(ApiError::InvalidRequestError(code)) => code,
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn no_policy() {
+        assert_eq!(refusal("example.com", &set(&[]), &set(&[]), None), None);
+    }
+
+    #[test]
+    fn allowlist() {
+        let allowed = set(&["Example.com"]);
+        assert_eq!(refusal("example.com", &allowed, &set(&[]), None), None);
+        assert_eq!(refusal("eu.example.com", &allowed, &set(&[]), None), None);
+        assert_eq!(
+            refusal("example.org", &allowed, &set(&[]), None).as_deref(),
+            Some("domain_not_allowed")
+        );
+        // A suffix isn't a parent domain
+        assert_eq!(
+            refusal("badexample.com", &allowed, &set(&[]), None).as_deref(),
+            Some("domain_not_allowed")
+        );
+    }
+
+    #[test]
+    fn denylist() {
+        let denied = set(&["spam.example"]);
+        assert_eq!(
+            refusal("SPAM.example", &set(&[]), &denied, None).as_deref(),
+            Some("domain_denied")
+        );
+        assert_eq!(
+            refusal("mx.spam.example", &set(&[]), &denied, None).as_deref(),
+            Some("domain_denied")
+        );
+        assert_eq!(refusal("ham.example", &set(&[]), &denied, None), None);
+        // Denied even if it's allowed too
+        assert_eq!(
+            refusal("spam.example", &set(&["spam.example"]), &denied, None).as_deref(),
+            Some("domain_denied")
+        );
+    }
+
+    #[test]
+    fn top_level_domain_is_never_matched() {
+        assert_eq!(
+            refusal("example.com", &set(&[]), &set(&["com"]), None),
+            None
+        );
+    }
+
+    #[test]
+    fn disposable_domains() {
+        let bundled = parse_domains(BUNDLED_DISPOSABLE);
+        assert!(bundled.contains("guerrillamail.com"));
+        assert!(!bundled.iter().any(|domain| domain.starts_with('#')));
+
+        assert_eq!(
+            refusal("guerrillamail.com", &set(&[]), &set(&[]), Some(&bundled)).as_deref(),
+            Some("disposable_email")
+        );
+        assert_eq!(
+            refusal("a.guerrillamail.com", &set(&[]), &set(&[]), Some(&bundled)).as_deref(),
+            Some("disposable_email")
+        );
+        assert_eq!(
+            refusal("example.com", &set(&[]), &set(&[]), Some(&bundled)),
+            None
+        );
+        // Allowed when they're not blocked
+        assert_eq!(
+            refusal("guerrillamail.com", &set(&[]), &set(&[]), None),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_list() {
+        let parsed = parse_domains("# comment\n\n  Trash.example  # inline\nother.example\n");
+        assert_eq!(parsed, set(&["trash.example", "other.example"]));
+    }
+}
@@ -0,0 +1,99 @@
+use lettre::Address;
+use sproot::apierrors::ApiError;
+
+use super::config::LocalPartPolicy;
+use crate::CONFIG;
+
+/// Normalize the email so that an address has a single identity: trim it,
+/// convert its domain to lowercase ASCII (IDN) and apply the local part
+/// policy. Only bare addresses are accepted, not the `Bob <bob@x>` forms.
+pub fn normalize_email(raw: &str) -> Result<String, ApiError> {
+    normalize_email_with(raw, CONFIG.email_local_part)
+}
+
+/// Normalize the email as normalize_email does, using the given policy
+pub fn normalize_email_with(raw: &str, policy: LocalPartPolicy) -> Result<String, ApiError> {
+    let (local, domain) = raw
+        .trim()
+        .rsplit_once('@')
+        .ok_or(ApiError::InvalidRequestError(None))?;
+
+    if local.is_empty() {
+        return Err(ApiError::InvalidRequestError(None));
+    }
+
+    // domain_to_ascii also lowercase the domain
+    let domain = idna::domain_to_ascii(domain).map_err(|_| ApiError::InvalidRequestError(None))?;
+    let local = match policy {
+        LocalPartPolicy::Lowercase => local.to_lowercase(),
+        LocalPartPolicy::Preserve => local.to_owned(),
+    };
+
+    let email = format!("{}@{}", local, domain);
+    // This act as the email verification (addr-spec only)
+    email
+        .parse::<Address>()
+        .map_err(|_| ApiError::InvalidRequestError(None))?;
+
+    Ok(email)
+}
+
+/// Domain of a (normalized) email
+pub fn email_domain(email: &str) -> &str {
+    email.rsplit_once('@').map_or("", |(_, domain)| domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowercase_policy() {
+        assert_eq!(
+            normalize_email_with("  Jane.Doe@Example.COM ", LocalPartPolicy::Lowercase).unwrap(),
+            "jane.doe@example.com"
+        );
+    }
+
+    #[test]
+    fn preserve_policy() {
+        assert_eq!(
+            normalize_email_with("Jane.Doe@Example.COM", LocalPartPolicy::Preserve).unwrap(),
+            "Jane.Doe@example.com"
+        );
+    }
+
+    #[test]
+    fn idn_domain() {
+        let unicode = normalize_email_with("bob@Bücher.example", LocalPartPolicy::Lowercase);
+        let punycode =
+            normalize_email_with("bob@xn--bcher-kva.example", LocalPartPolicy::Lowercase);
+        assert_eq!(unicode.unwrap(), "bob@xn--bcher-kva.example");
+        assert_eq!(punycode.unwrap(), "bob@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn last_at_splits() {
+        assert_eq!(
+            normalize_email_with("\"a@b\"@example.com", LocalPartPolicy::Lowercase).unwrap(),
+            "\"a@b\"@example.com"
+        );
+    }
+
+    #[test]
+    fn invalid_emails() {
+        for raw in ["", "bob", "@example.com", "Bob <bob@example.com>", "bob@"] {
+            assert!(
+                normalize_email_with(raw, LocalPartPolicy::Lowercase).is_err(),
+                "{} should be refused",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn domain_of_email() {
+        assert_eq!(email_domain("bob@example.com"), "example.com");
+        assert_eq!(email_domain("bob"), "");
+    }
+}
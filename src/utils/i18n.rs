@@ -0,0 +1,152 @@
+use actix_web::{http::header, HttpRequest};
+use serde::{Deserialize, Serialize};
+
+use crate::CONFIG;
+
+/// Languages the mails are translated to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+}
+
+impl Locale {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Fr => "fr",
+        }
+    }
+
+    /// Translation of the language tag, trying the tag ("fr-CA", "fr_CA")
+    /// then its primary language ("fr") as only the latter are translated.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let tag = tag.trim().to_ascii_lowercase().replace('_', "-");
+        let primary = tag.split('-').next().unwrap_or_default();
+
+        [tag.as_str(), primary]
+            .iter()
+            .find_map(|candidate| match *candidate {
+                "en" => Some(Locale::En),
+                "fr" => Some(Locale::Fr),
+                _ => None,
+            })
+    }
+
+    /// Locale of the mails for the preferred language tag (if any): its
+    /// translation, falling back to the default_locale then to english.
+    pub fn resolve(preferred: Option<&str>) -> Self {
+        Self::resolve_with(preferred, &CONFIG.default_locale)
+    }
+
+    /// Locale as resolve finds it, falling back to the given default_locale
+    pub fn resolve_with(preferred: Option<&str>, default_locale: &str) -> Self {
+        preferred
+            .and_then(Self::from_tag)
+            .or_else(|| Self::from_tag(default_locale))
+            .unwrap_or_default()
+    }
+
+    /// Best translated locale of the Accept-Language header of the request
+    pub fn from_request(request: &HttpRequest) -> Option<Self> {
+        let accepted = request
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)?
+            .to_str()
+            .ok()?;
+
+        let mut ranges: Vec<(&str, f32)> = accepted
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+                Some((tag, quality))
+            })
+            .filter(|(tag, quality)| !tag.is_empty() && *tag != "*" && *quality > 0.0)
+            .collect();
+        // Stable sort: the order of the header breaks the ties
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        ranges.into_iter().find_map(|(tag, _)| Self::from_tag(tag))
+    }
+}
+
+/// Check that the value looks like a language tag (eg: en, fr-CA)
+pub fn is_language_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= 35
+        && tag.split(['-', '_']).all(|subtag| {
+            !subtag.is_empty()
+                && subtag.len() <= 8
+                && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn accepted(header: &str) -> Option<Locale> {
+        Locale::from_request(
+            &TestRequest::default()
+                .insert_header((header::ACCEPT_LANGUAGE, header))
+                .to_http_request(),
+        )
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(Locale::from_tag("fr"), Some(Locale::Fr));
+        assert_eq!(Locale::from_tag(" FR-ca "), Some(Locale::Fr));
+        assert_eq!(Locale::from_tag("fr_CA"), Some(Locale::Fr));
+        assert_eq!(Locale::from_tag("en-GB"), Some(Locale::En));
+        assert_eq!(Locale::from_tag("de"), None);
+        assert_eq!(Locale::from_tag(""), None);
+    }
+
+    #[test]
+    fn resolution_fallback() {
+        assert_eq!(Locale::resolve_with(Some("fr-BE"), "en"), Locale::Fr);
+        // Untranslated preference, the default locale then english
+        assert_eq!(Locale::resolve_with(Some("de"), "fr"), Locale::Fr);
+        assert_eq!(Locale::resolve_with(None, "fr"), Locale::Fr);
+        assert_eq!(Locale::resolve_with(Some("de"), "it"), Locale::En);
+        assert_eq!(Locale::resolve_with(None, ""), Locale::En);
+    }
+
+    #[test]
+    fn accept_language() {
+        assert_eq!(accepted("fr-CH, fr;q=0.9, en;q=0.8"), Some(Locale::Fr));
+        assert_eq!(accepted("en;q=0.5, fr;q=0.8"), Some(Locale::Fr));
+        // The untranslated ranges are skipped
+        assert_eq!(accepted("de-DE, de;q=0.9, en;q=0.7"), Some(Locale::En));
+        // Ties keep the order of the header
+        assert_eq!(accepted("en;q=0.8, fr;q=0.8"), Some(Locale::En));
+        // Refused (q=0), wildcard and invalid ranges
+        assert_eq!(accepted("fr;q=0, en;q=0.1"), Some(Locale::En));
+        assert_eq!(accepted("*"), None);
+        assert_eq!(accepted("fr;q=abc"), None);
+        assert_eq!(
+            Locale::from_request(&TestRequest::default().to_http_request()),
+            None
+        );
+    }
+
+    #[test]
+    fn language_tags() {
+        assert!(is_language_tag("en"));
+        assert!(is_language_tag("fr-CA"));
+        assert!(is_language_tag("zh_Hant_TW"));
+        assert!(!is_language_tag(""));
+        assert!(!is_language_tag("fr-"));
+        assert!(!is_language_tag("en;q=1"));
+        assert!(!is_language_tag("toolongsubtag"));
+    }
+}
@@ -1,18 +1,144 @@
-use chrono::Utc;
-use jsonwebtoken::{decode, encode, Algorithm, Header, Validation};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 use sproot::apierrors::ApiError;
 
-use crate::{JWT_DECODINGKEY, JWT_ENCODINGKEY};
+use super::config::JwtAlgorithm;
+use crate::{CONFIG, JWT_DECODINGKEY, JWT_ENCODINGKEY};
+
+/// What a token can be used for, so that a token issued
+/// for one flow cannot be replayed against another one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Purpose {
+    // Tokens issued before the purpose existed were all sso tokens
+    #[default]
+    Sso,
+    Invite,
+    Register,
+    Challenge,
+    Deletion,
+    EmailConfirm,
+    EmailCancel,
+    Revoke,
+}
+
+/// Keys and claims the tokens are signed and verified with,
+/// the ones of the config (from_config) outside of the tests.
+pub struct JwtKeys<'a> {
+    pub algorithm: Algorithm,
+    pub encoding: &'a EncodingKey,
+    pub decoding: &'a DecodingKey,
+    pub issuer: &'a str,
+    pub audience: &'a str,
+    pub leeway_secs: u64,
+}
+
+impl JwtKeys<'static> {
+    pub fn from_config() -> Self {
+        Self {
+            algorithm: algorithm(),
+            encoding: &JWT_ENCODINGKEY,
+            decoding: &JWT_DECODINGKEY,
+            issuer: &CONFIG.jwt.issuer,
+            audience: &CONFIG.jwt.audience,
+            leeway_secs: CONFIG.jwt.leeway_secs,
+        }
+    }
+}
+
+fn algorithm() -> Algorithm {
+    match CONFIG.jwt.algorithm {
+        JwtAlgorithm::ES256 => Algorithm::ES256,
+        JwtAlgorithm::ES384 => Algorithm::ES384,
+        JwtAlgorithm::EdDSA => Algorithm::EdDSA,
+        JwtAlgorithm::RS256 => Algorithm::RS256,
+        JwtAlgorithm::PS256 => Algorithm::PS256,
+    }
+}
+
+/// Load the private key (PEM) of jwt_priv for the algorithm
+pub fn load_encoding_key() -> Result<EncodingKey, String> {
+    let pem = std::fs::read(&CONFIG.jwt_priv).map_err(|err| err.to_string())?;
+    match CONFIG.jwt.algorithm {
+        JwtAlgorithm::ES256 | JwtAlgorithm::ES384 => EncodingKey::from_ec_pem(&pem),
+        JwtAlgorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+        JwtAlgorithm::RS256 | JwtAlgorithm::PS256 => EncodingKey::from_rsa_pem(&pem),
+    }
+    .map_err(|err| err.to_string())
+}
+
+/// Load the public key (PEM) of jwt_pub for the algorithm
+pub fn load_decoding_key() -> Result<DecodingKey, String> {
+    let pem = std::fs::read(&CONFIG.jwt_pub).map_err(|err| err.to_string())?;
+    match CONFIG.jwt.algorithm {
+        JwtAlgorithm::ES256 | JwtAlgorithm::ES384 => DecodingKey::from_ec_pem(&pem),
+        JwtAlgorithm::EdDSA => DecodingKey::from_ed_pem(&pem),
+        JwtAlgorithm::RS256 | JwtAlgorithm::PS256 => DecodingKey::from_rsa_pem(&pem),
+    }
+    .map_err(|err| err.to_string())
+}
+
+/// Check that the keys are a pair usable with the algorithm by
+/// signing then verifying a token, exiting otherwise.
+pub fn test_keys() {
+    let res = create_purpose_jwt(Purpose::Challenge, "test", Duration::minutes(1))
+        .and_then(|jwt| decode_purpose_jwt(Purpose::Challenge, &jwt));
+
+    match res {
+        Ok(_) => info!("JWT: tokens signed using {:?}", algorithm()),
+        Err(_) => {
+            error!(
+                "JWT: cannot sign and verify a token using {:?}, check the keys",
+                algorithm()
+            );
+            std::process::exit(1);
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Claims {
     sub: String,
+    iss: String,
+    aud: String,
+    iat: usize,
     exp: usize,
+    #[serde(default)]
+    pur: Purpose,
 }
 
+/// Create the token of the magic link, valid for jwt.magic_link_minutes
 pub fn create_jwt(customer_id: &str) -> Result<String, ApiError> {
-    let expiration = match Utc::now().checked_add_signed(chrono::Duration::minutes(5)) {
+    create_purpose_jwt(
+        Purpose::Sso,
+        customer_id,
+        Duration::minutes(CONFIG.jwt.magic_link_minutes),
+    )
+}
+
+pub fn decode_jwt(jwt: &str) -> Result<String, ApiError> {
+    decode_purpose_jwt(Purpose::Sso, jwt)
+}
+
+/// Create a token for the purpose, valid for the given duration
+pub fn create_purpose_jwt(
+    purpose: Purpose,
+    subject: &str,
+    validity: Duration,
+) -> Result<String, ApiError> {
+    create_purpose_jwt_with(&JwtKeys::from_config(), purpose, subject, validity)
+}
+
+/// Create the token as create_purpose_jwt does, using the given keys
+pub fn create_purpose_jwt_with(
+    keys: &JwtKeys,
+    purpose: Purpose,
+    subject: &str,
+    validity: Duration,
+) -> Result<String, ApiError> {
+    let now = Utc::now();
+    let expiration = match now.checked_add_signed(validity) {
         Some(time) => time.timestamp(),
         None => {
             return Err(ApiError::ServerError(None));
@@ -20,22 +146,58 @@ pub fn create_jwt(customer_id: &str) -> Result<String, ApiError> {
     };
 
     let claims = Claims {
-        sub: customer_id.to_owned(),
+        sub: subject.to_owned(),
+        iss: keys.issuer.to_owned(),
+        aud: keys.audience.to_owned(),
+        iat: now.timestamp() as usize,
         exp: expiration as usize,
+        pur: purpose,
     };
 
-    encode(&Header::new(Algorithm::ES256), &claims, &JWT_ENCODINGKEY).map_err(|err| {
+    encode(&Header::new(keys.algorithm), &claims, keys.encoding).map_err(|err| {
         trace!("jwt encode error: {}", err);
         ApiError::ServerError(None)
     })
 }
 
-pub fn decode_jwt(jwt: &str) -> Result<String, ApiError> {
-    let decoded = decode::<Claims>(jwt, &JWT_DECODINGKEY, &Validation::new(Algorithm::ES256))
-        .map_err(|err| {
-            trace!("jwt decode error: {}", err);
-            ApiError::AuthorizationError(None)
-        })?;
+/// Validation of the tokens: every claim is required, the issuer and the
+/// audience must match the keys' ones and the leeway applies to the expiry.
+fn validation(keys: &JwtKeys) -> Validation {
+    let mut validation = Validation::new(keys.algorithm);
+    validation.leeway = keys.leeway_secs;
+    validation.set_issuer(&[keys.issuer]);
+    validation.set_audience(&[keys.audience]);
+    validation.set_required_spec_claims(&["sub", "iss", "aud", "iat", "exp"]);
+    validation
+}
+
+/// Decode the token and return its subject if it was issued for the purpose
+pub fn decode_purpose_jwt(purpose: Purpose, jwt: &str) -> Result<String, ApiError> {
+    decode_purpose_jwt_with(&JwtKeys::from_config(), purpose, jwt)
+}
+
+/// Decode the token as decode_purpose_jwt does, using the given keys
+pub fn decode_purpose_jwt_with(
+    keys: &JwtKeys,
+    purpose: Purpose,
+    jwt: &str,
+) -> Result<String, ApiError> {
+    let decoded = decode::<Claims>(jwt, keys.decoding, &validation(keys)).map_err(|err| {
+        trace!("jwt decode error: {}", err);
+        ApiError::AuthorizationError(None)
+    })?;
+
+    // Not checked by the Validation, a token can't be issued in the future
+    let now = Utc::now().timestamp() as u64;
+    if decoded.claims.iat as u64 > now + keys.leeway_secs {
+        trace!("jwt issued in the future: {}", decoded.claims.iat);
+        return Err(ApiError::AuthorizationError(None));
+    }
+
+    if decoded.claims.pur != purpose {
+        trace!("jwt purpose mismatch: {:?}", decoded.claims.pur);
+        return Err(ApiError::AuthorizationError(None));
+    }
 
     Ok(decoded.claims.sub)
 }
@@ -0,0 +1,125 @@
+use chrono::Duration;
+use lettre::message::Mailbox;
+use sailfish::TemplateOnce;
+use serde::Serialize;
+use sproot::apierrors::ApiError;
+
+use super::{
+    config::Branding,
+    i18n::Locale,
+    mailer::{send_mail, RenderedMail},
+    templates::render_template,
+};
+use crate::{ConnType, CONFIG};
+
+/// Validity of the link confirming the deletion
+pub const DELETION_LINK_MINUTES: i64 = 15;
+
+/// Structure representing the deletion confirmation template html sent by mail
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "delete.stpl")]
+struct DeleteTemplate<'a> {
+    brand: &'a Branding,
+    grace_days: i64,
+    link: &'a str,
+}
+
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "fr/delete.stpl")]
+struct DeleteTemplateFr<'a> {
+    brand: &'a Branding,
+    grace_days: i64,
+    link: &'a str,
+}
+
+/// Structure representing the final deletion notice template html sent by mail
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "deleted.stpl")]
+struct DeletedTemplate<'a> {
+    brand: &'a Branding,
+}
+
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "fr/deleted.stpl")]
+struct DeletedTemplateFr<'a> {
+    brand: &'a Branding,
+}
+
+/// Send the link to confirm the deletion of the account,
+/// jwt being the (base64 encoded) signed deletion token.
+pub fn send_deletion_mail(
+    conn: &mut ConnType,
+    email: Mailbox,
+    locale: Locale,
+    jwt: &str,
+) -> Result<(), ApiError> {
+    send_mail(conn, email, render_deletion_mail(locale, jwt)?)
+}
+
+/// Subject and bodies of the mail sent by send_deletion_mail
+pub fn render_deletion_mail(locale: Locale, jwt: &str) -> Result<RenderedMail, ApiError> {
+    let brand = &CONFIG.branding;
+    let product = &brand.product_name;
+    let link = format!("{}/delete?jwt={}", CONFIG.sso_base_url, jwt);
+    let grace_days = CONFIG.deletion_grace_days;
+
+    let (subject, plain, delete_template) = match locale {
+        Locale::Fr => (
+            format!("{product} - Confirmez la suppression de votre compte"),
+            format!(
+                "{product} - Utilisez le lien suivant pour confirmer la suppression de votre compte (valable {} minutes), il sera définitivement supprimé après {} jours : {}",
+                DELETION_LINK_MINUTES, grace_days, link
+            ),
+            render_template("fr/delete.stpl", DeleteTemplateFr { brand, grace_days, link: &link })?,
+        ),
+        _ => (
+            format!("{product} - Confirm the deletion of your account"),
+            format!(
+                "{product} - Use the following link to confirm the deletion of your account (valid {} minutes), it will be permanently deleted after {} days: {}",
+                DELETION_LINK_MINUTES, grace_days, link
+            ),
+            render_template("delete.stpl", DeleteTemplate { brand, grace_days, link: &link })?,
+        ),
+    };
+
+    Ok(RenderedMail {
+        subject,
+        plain,
+        html: Some(delete_template),
+        expires_in: Some(Duration::minutes(DELETION_LINK_MINUTES)),
+    })
+}
+
+/// Send the notice that the account has been permanently deleted
+pub fn send_deleted_mail(
+    conn: &mut ConnType,
+    email: Mailbox,
+    locale: Locale,
+) -> Result<(), ApiError> {
+    send_mail(conn, email, render_deleted_mail(locale)?)
+}
+
+/// Subject and bodies of the mail sent by send_deleted_mail
+pub fn render_deleted_mail(locale: Locale) -> Result<RenderedMail, ApiError> {
+    let brand = &CONFIG.branding;
+    let product = &brand.product_name;
+    let (subject, plain, deleted_template) = match locale {
+        Locale::Fr => (
+            format!("{product} - Votre compte a été supprimé"),
+            format!("{product} - Votre compte, vos clés et vos données ont été définitivement supprimés."),
+            render_template("fr/deleted.stpl", DeletedTemplateFr { brand })?,
+        ),
+        _ => (
+            format!("{product} - Your account has been deleted"),
+            format!("{product} - Your account, your keys and your data have been permanently deleted."),
+            render_template("deleted.stpl", DeletedTemplate { brand })?,
+        ),
+    };
+
+    Ok(RenderedMail {
+        subject,
+        plain,
+        html: Some(deleted_template),
+        expires_in: None,
+    })
+}
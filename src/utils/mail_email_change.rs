@@ -0,0 +1,192 @@
+use chrono::{Duration, NaiveDateTime};
+use lettre::message::Mailbox;
+use sailfish::TemplateOnce;
+use serde::Serialize;
+use sproot::apierrors::ApiError;
+
+use super::{
+    config::Branding,
+    i18n::Locale,
+    mailer::{send_mail, RenderedMail},
+    templates::render_template,
+};
+use crate::{ConnType, CONFIG};
+
+/// Validity of the links confirming and cancelling an email change
+pub const EMAIL_CHANGE_HOURS: i64 = 1;
+
+/// Structure representing the confirmation template html sent to the new email
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "email_confirm.stpl")]
+struct EmailConfirmTemplate<'a> {
+    brand: &'a Branding,
+    expires_at: &'a str,
+    link: &'a str,
+}
+
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "fr/email_confirm.stpl")]
+struct EmailConfirmTemplateFr<'a> {
+    brand: &'a Branding,
+    expires_at: &'a str,
+    link: &'a str,
+}
+
+/// Structure representing the notice template html sent to the old email
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "email_notice.stpl")]
+struct EmailNoticeTemplate<'a> {
+    brand: &'a Branding,
+    new_email: &'a str,
+    expires_at: &'a str,
+    link: &'a str,
+}
+
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "fr/email_notice.stpl")]
+struct EmailNoticeTemplateFr<'a> {
+    brand: &'a Branding,
+    new_email: &'a str,
+    expires_at: &'a str,
+    link: &'a str,
+}
+
+/// Send the link to confirm the new email to the new address,
+/// jwt being the (base64 encoded) signed confirmation token.
+pub fn send_email_confirm_mail(
+    conn: &mut ConnType,
+    email: Mailbox,
+    locale: Locale,
+    expires_at: &NaiveDateTime,
+    jwt: &str,
+) -> Result<(), ApiError> {
+    send_mail(
+        conn,
+        email,
+        render_email_confirm_mail(locale, expires_at, jwt)?,
+    )
+}
+
+/// Subject and bodies of the mail sent by send_email_confirm_mail
+pub fn render_email_confirm_mail(
+    locale: Locale,
+    expires_at: &NaiveDateTime,
+    jwt: &str,
+) -> Result<RenderedMail, ApiError> {
+    let brand = &CONFIG.branding;
+    let product = &brand.product_name;
+    let link = format!("{}/email/confirm?jwt={}", CONFIG.sso_base_url, jwt);
+    let expires_at = expires_at.format("%Y-%m-%d %H:%M").to_string();
+
+    let (subject, plain, confirm_template) = match locale {
+        Locale::Fr => (
+            format!("{product} - Confirmez votre nouvel email"),
+            format!(
+                "{product} - Utilisez le lien suivant pour confirmer votre nouvel email (valable jusqu'au {} utc) : {}",
+                expires_at, link
+            ),
+            render_template(
+                "fr/email_confirm.stpl",
+                EmailConfirmTemplateFr {
+                    brand,
+                    expires_at: &expires_at,
+                    link: &link,
+                },
+            )?,
+        ),
+        _ => (
+            format!("{product} - Confirm your new email"),
+            format!(
+                "{product} - Use the following link to confirm your new email (valid until {} utc): {}",
+                expires_at, link
+            ),
+            render_template(
+                "email_confirm.stpl",
+                EmailConfirmTemplate {
+                    brand,
+                    expires_at: &expires_at,
+                    link: &link,
+                },
+            )?,
+        ),
+    };
+
+    Ok(RenderedMail {
+        subject,
+        plain,
+        html: Some(confirm_template),
+        expires_in: Some(Duration::hours(EMAIL_CHANGE_HOURS)),
+    })
+}
+
+/// Notify the old address of the change, with a link to cancel it,
+/// jwt being the (base64 encoded) signed cancellation token.
+pub fn send_email_notice_mail(
+    conn: &mut ConnType,
+    email: Mailbox,
+    locale: Locale,
+    new_email: &str,
+    expires_at: &NaiveDateTime,
+    jwt: &str,
+) -> Result<(), ApiError> {
+    send_mail(
+        conn,
+        email,
+        render_email_notice_mail(locale, new_email, expires_at, jwt)?,
+    )
+}
+
+/// Subject and bodies of the mail sent by send_email_notice_mail
+pub fn render_email_notice_mail(
+    locale: Locale,
+    new_email: &str,
+    expires_at: &NaiveDateTime,
+    jwt: &str,
+) -> Result<RenderedMail, ApiError> {
+    let brand = &CONFIG.branding;
+    let product = &brand.product_name;
+    let link = format!("{}/email/cancel?jwt={}", CONFIG.sso_base_url, jwt);
+    let expires_at = expires_at.format("%Y-%m-%d %H:%M").to_string();
+
+    let (subject, plain, notice_template) = match locale {
+        Locale::Fr => (
+            format!("{product} - Votre email va changer"),
+            format!(
+                "{product} - Le changement de votre email pour {} a été demandé. Si ce n'était pas vous, utilisez le lien suivant pour l'annuler : {}",
+                new_email, link
+            ),
+            render_template(
+                "fr/email_notice.stpl",
+                EmailNoticeTemplateFr {
+                    brand,
+                    new_email,
+                    expires_at: &expires_at,
+                    link: &link,
+                },
+            )?,
+        ),
+        _ => (
+            format!("{product} - Your email is changing"),
+            format!(
+                "{product} - A change of your email to {} was requested. If it wasn't you, use the following link to cancel it: {}",
+                new_email, link
+            ),
+            render_template(
+                "email_notice.stpl",
+                EmailNoticeTemplate {
+                    brand,
+                    new_email,
+                    expires_at: &expires_at,
+                    link: &link,
+                },
+            )?,
+        ),
+    };
+
+    Ok(RenderedMail {
+        subject,
+        plain,
+        html: Some(notice_template),
+        expires_in: Some(Duration::hours(EMAIL_CHANGE_HOURS)),
+    })
+}
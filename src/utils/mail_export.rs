@@ -0,0 +1,95 @@
+use chrono::{NaiveDateTime, Utc};
+use lettre::message::Mailbox;
+use sailfish::TemplateOnce;
+use serde::Serialize;
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use super::{
+    config::Branding,
+    i18n::Locale,
+    mailer::{send_mail, RenderedMail},
+    templates::render_template,
+};
+use crate::{ConnType, CONFIG};
+
+/// Structure representing the export template html sent by mail
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "export.stpl")]
+struct ExportTemplate<'a> {
+    brand: &'a Branding,
+    expires_at: &'a str,
+    link: &'a str,
+}
+
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "fr/export.stpl")]
+struct ExportTemplateFr<'a> {
+    brand: &'a Branding,
+    expires_at: &'a str,
+    link: &'a str,
+}
+
+/// Send the link to download the export (eid) once it's ready
+pub fn send_export_mail(
+    conn: &mut ConnType,
+    email: Mailbox,
+    locale: Locale,
+    eid: &Uuid,
+    expires_at: &NaiveDateTime,
+) -> Result<(), ApiError> {
+    send_mail(conn, email, render_export_mail(locale, eid, expires_at)?)
+}
+
+/// Subject and bodies of the mail sent by send_export_mail
+pub fn render_export_mail(
+    locale: Locale,
+    eid: &Uuid,
+    expires_at: &NaiveDateTime,
+) -> Result<RenderedMail, ApiError> {
+    let brand = &CONFIG.branding;
+    let product = &brand.product_name;
+    let link = format!("{}/export?uuid={}", CONFIG.sso_base_url, eid);
+    let expires_in = *expires_at - Utc::now().naive_utc();
+    let expires_at = expires_at.format("%Y-%m-%d %H:%M").to_string();
+
+    let (subject, plain, export_template) = match locale {
+        Locale::Fr => (
+            format!("{product} - Votre export de données est prêt"),
+            format!(
+                "{product} - L'export de vos données est prêt, utilisez le lien suivant pour le télécharger (disponible jusqu'au {} utc) : {}",
+                expires_at, link
+            ),
+            render_template(
+                "fr/export.stpl",
+                ExportTemplateFr {
+                    brand,
+                    expires_at: &expires_at,
+                    link: &link,
+                },
+            )?,
+        ),
+        _ => (
+            format!("{product} - Your data export is ready"),
+            format!(
+                "{product} - The export of your data is ready, use the following link to download it (available until {} utc): {}",
+                expires_at, link
+            ),
+            render_template(
+                "export.stpl",
+                ExportTemplate {
+                    brand,
+                    expires_at: &expires_at,
+                    link: &link,
+                },
+            )?,
+        ),
+    };
+
+    Ok(RenderedMail {
+        subject,
+        plain,
+        html: Some(export_template),
+        expires_in: Some(expires_in),
+    })
+}
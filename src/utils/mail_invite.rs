@@ -0,0 +1,117 @@
+use chrono::{Duration, NaiveDateTime};
+use lettre::message::Mailbox;
+use sailfish::TemplateOnce;
+use serde::Serialize;
+use sproot::apierrors::ApiError;
+
+use super::{
+    config::Branding,
+    i18n::Locale,
+    mailer::{send_mail, RenderedMail},
+    templates::render_template,
+};
+use crate::{ConnType, CONFIG};
+
+/// Structure representing the invitation template html sent by mail
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "invite.stpl")]
+struct InviteTemplate<'a> {
+    brand: &'a Branding,
+    inviter: &'a str,
+    org_name: &'a str,
+    role: &'a str,
+    expires_at: &'a str,
+    link: &'a str,
+}
+
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "fr/invite.stpl")]
+struct InviteTemplateFr<'a> {
+    brand: &'a Branding,
+    inviter: &'a str,
+    org_name: &'a str,
+    role: &'a str,
+    expires_at: &'a str,
+    link: &'a str,
+}
+
+/// Send the invitation to join the organization org_name,
+/// jwt being the (base64 encoded) signed invitation token.
+#[allow(clippy::too_many_arguments)]
+pub fn send_invite_mail(
+    conn: &mut ConnType,
+    email: Mailbox,
+    locale: Locale,
+    org_name: &str,
+    inviter: &str,
+    role: &str,
+    expires_at: &NaiveDateTime,
+    jwt: &str,
+) -> Result<(), ApiError> {
+    send_mail(
+        conn,
+        email,
+        render_invite_mail(locale, org_name, inviter, role, expires_at, jwt)?,
+    )
+}
+
+/// Subject and bodies of the mail sent by send_invite_mail
+pub fn render_invite_mail(
+    locale: Locale,
+    org_name: &str,
+    inviter: &str,
+    role: &str,
+    expires_at: &NaiveDateTime,
+    jwt: &str,
+) -> Result<RenderedMail, ApiError> {
+    let brand = &CONFIG.branding;
+    let product = &brand.product_name;
+    let link = format!("{}/join?jwt={}", CONFIG.sso_base_url, jwt);
+    let expires_at = expires_at.format("%Y-%m-%d %H:%M").to_string();
+
+    let (subject, plain, invite_template) = match locale {
+        Locale::Fr => (
+            format!("{product} - Vous êtes invité à rejoindre {}", org_name),
+            format!(
+                "{product} - {} vous invite à rejoindre {} en tant que {}. Utilisez le lien suivant pour accepter l'invitation (valable jusqu'au {} utc) : {}",
+                inviter, org_name, role, expires_at, link
+            ),
+            render_template(
+                "fr/invite.stpl",
+                InviteTemplateFr {
+                    brand,
+                    inviter,
+                    org_name,
+                    role,
+                    expires_at: &expires_at,
+                    link: &link,
+                },
+            )?,
+        ),
+        _ => (
+            format!("{product} - You've been invited to join {}", org_name),
+            format!(
+                "{product} - {} invited you to join {} as {}. Use the following link to accept the invitation (valid until {} utc): {}",
+                inviter, org_name, role, expires_at, link
+            ),
+            render_template(
+                "invite.stpl",
+                InviteTemplate {
+                    brand,
+                    inviter,
+                    org_name,
+                    role,
+                    expires_at: &expires_at,
+                    link: &link,
+                },
+            )?,
+        ),
+    };
+
+    Ok(RenderedMail {
+        subject,
+        plain,
+        html: Some(invite_template),
+        expires_in: Some(Duration::hours(CONFIG.invite_validity_hours)),
+    })
+}
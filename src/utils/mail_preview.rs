@@ -0,0 +1,107 @@
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sproot::apierrors::ApiError;
+use uuid::Uuid;
+
+use super::{
+    audit::RequestMeta,
+    i18n::Locale,
+    mail_deletion::{render_deleted_mail, render_deletion_mail},
+    mail_email_change::{render_email_confirm_mail, render_email_notice_mail},
+    mail_export::render_export_mail,
+    mail_invite::render_invite_mail,
+    mail_security::render_security_mail,
+    mail_sso::{render_register_mail, render_sso_mail},
+    mailer::RenderedMail,
+    notifications::{NotificationKind, SecurityEvent},
+};
+
+/// Token put in the links of the previews, which are never valid
+const SAMPLE_JWT: &str = "preview-token";
+
+/// Mails which can be previewed by the admins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MailTemplate {
+    Sso,
+    Register,
+    Invite,
+    Export,
+    Deletion,
+    Deleted,
+    EmailConfirm,
+    EmailNotice,
+    Security,
+}
+
+impl MailTemplate {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailTemplate::Sso => "sso",
+            MailTemplate::Register => "register",
+            MailTemplate::Invite => "invite",
+            MailTemplate::Export => "export",
+            MailTemplate::Deletion => "deletion",
+            MailTemplate::Deleted => "deleted",
+            MailTemplate::EmailConfirm => "email-confirm",
+            MailTemplate::EmailNotice => "email-notice",
+            MailTemplate::Security => "security",
+        }
+    }
+}
+
+/// Event of the security mail previewed for the kind
+fn sample_event(kind: NotificationKind) -> SecurityEvent {
+    match kind {
+        NotificationKind::NewLogin => SecurityEvent::NewLogin,
+        NotificationKind::KeyCreate => SecurityEvent::KeyCreate { key_id: 42 },
+        NotificationKind::KeyDelete => SecurityEvent::KeyDelete { key_id: 42 },
+        NotificationKind::HostBind => SecurityEvent::HostBind {
+            key_id: 42,
+            host_uuid: Uuid::nil().to_string(),
+        },
+        NotificationKind::EmailChange => SecurityEvent::EmailChange {
+            old_email: "jane@example.com".to_owned(),
+            new_email: "jane.doe@example.com".to_owned(),
+        },
+    }
+}
+
+/// Render the mail in the locale using sample values, the
+/// kind selecting the event of the security notification.
+pub fn render_sample(
+    template: MailTemplate,
+    locale: Locale,
+    kind: NotificationKind,
+) -> Result<RenderedMail, ApiError> {
+    let expires_at = Utc::now().naive_utc() + Duration::hours(1);
+
+    match template {
+        MailTemplate::Sso => render_sso_mail(locale, SAMPLE_JWT),
+        MailTemplate::Register => render_register_mail(locale, SAMPLE_JWT),
+        MailTemplate::Invite => render_invite_mail(
+            locale,
+            "Acme",
+            "jane@example.com",
+            "member",
+            &(Utc::now().naive_utc() + Duration::days(7)),
+            SAMPLE_JWT,
+        ),
+        MailTemplate::Export => render_export_mail(locale, &Uuid::nil(), &expires_at),
+        MailTemplate::Deletion => render_deletion_mail(locale, SAMPLE_JWT),
+        MailTemplate::Deleted => render_deleted_mail(locale),
+        MailTemplate::EmailConfirm => render_email_confirm_mail(locale, &expires_at, SAMPLE_JWT),
+        MailTemplate::EmailNotice => {
+            render_email_notice_mail(locale, "jane.doe@example.com", &expires_at, SAMPLE_JWT)
+        }
+        MailTemplate::Security => render_security_mail(
+            locale,
+            &sample_event(kind),
+            &RequestMeta {
+                ip: Some("203.0.113.7".to_owned()),
+                user_agent: Some("Mozilla/5.0 (X11; Linux x86_64) Firefox/130.0".to_owned()),
+            },
+            SAMPLE_JWT,
+        ),
+    }
+}
@@ -0,0 +1,169 @@
+use chrono::{Duration, Utc};
+use lettre::message::Mailbox;
+use sailfish::TemplateOnce;
+use serde::Serialize;
+use sproot::apierrors::ApiError;
+
+use super::{
+    audit::RequestMeta,
+    config::Branding,
+    i18n::Locale,
+    mailer::{send_mail, RenderedMail},
+    notifications::{SecurityEvent, REVOKE_LINK_DAYS},
+    templates::render_template,
+};
+use crate::{ConnType, CONFIG};
+
+/// Structure representing the security notification template html sent by mail
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "security.stpl")]
+struct SecurityTemplate<'a> {
+    brand: &'a Branding,
+    title: &'a str,
+    message: &'a str,
+    ip: &'a str,
+    device: &'a str,
+    occurred_at: &'a str,
+    link: &'a str,
+}
+
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "fr/security.stpl")]
+struct SecurityTemplateFr<'a> {
+    brand: &'a Branding,
+    title: &'a str,
+    message: &'a str,
+    ip: &'a str,
+    device: &'a str,
+    occurred_at: &'a str,
+    link: &'a str,
+}
+
+/// Title and description of the event in the locale
+fn describe(locale: Locale, product: &str, event: &SecurityEvent) -> (String, String) {
+    match (locale, event) {
+        (Locale::Fr, SecurityEvent::NewLogin) => (
+            "Nouvelle connexion à votre compte".to_owned(),
+            format!("Une connexion à votre compte {product} a eu lieu depuis un nouvel appareil."),
+        ),
+        (Locale::Fr, SecurityEvent::KeyCreate { key_id }) => (
+            "Nouvelle clé d'API".to_owned(),
+            format!("Une clé d'API (n°{key_id}) a été créée sur votre compte {product}."),
+        ),
+        (Locale::Fr, SecurityEvent::KeyDelete { key_id }) => (
+            "Clé d'API supprimée".to_owned(),
+            format!("La clé d'API n°{key_id} a été supprimée de votre compte {product}."),
+        ),
+        (Locale::Fr, SecurityEvent::HostBind { key_id, host_uuid }) => (
+            "Clé d'API associée à un hôte".to_owned(),
+            format!("La clé d'API n°{key_id} de votre compte {product} a été associée à l'hôte {host_uuid}."),
+        ),
+        (Locale::Fr, SecurityEvent::EmailChange { new_email, .. }) => (
+            "Votre email a été modifié".to_owned(),
+            format!("L'email de votre compte {product} a été remplacé par {new_email}."),
+        ),
+        (_, SecurityEvent::NewLogin) => (
+            "New sign-in to your account".to_owned(),
+            format!("Your {product} account was signed in from a new device."),
+        ),
+        (_, SecurityEvent::KeyCreate { key_id }) => (
+            "New API key".to_owned(),
+            format!("An API key (#{key_id}) was created on your {product} account."),
+        ),
+        (_, SecurityEvent::KeyDelete { key_id }) => (
+            "API key deleted".to_owned(),
+            format!("The API key #{key_id} was deleted from your {product} account."),
+        ),
+        (_, SecurityEvent::HostBind { key_id, host_uuid }) => (
+            "API key bound to a host".to_owned(),
+            format!("The API key #{key_id} of your {product} account was bound to the host {host_uuid}."),
+        ),
+        (_, SecurityEvent::EmailChange { new_email, .. }) => (
+            "Your email was changed".to_owned(),
+            format!("The email of your {product} account was changed to {new_email}."),
+        ),
+    }
+}
+
+/// Notify the customer of the event (originating from meta), with a link
+/// to revoke all his sessions, jwt being the (base64 encoded) revoke token.
+pub fn send_security_mail(
+    conn: &mut ConnType,
+    email: Mailbox,
+    locale: Locale,
+    event: &SecurityEvent,
+    meta: &RequestMeta,
+    jwt: &str,
+) -> Result<(), ApiError> {
+    send_mail(conn, email, render_security_mail(locale, event, meta, jwt)?)
+}
+
+/// Subject and bodies of the mail sent by send_security_mail
+pub fn render_security_mail(
+    locale: Locale,
+    event: &SecurityEvent,
+    meta: &RequestMeta,
+    jwt: &str,
+) -> Result<RenderedMail, ApiError> {
+    let brand = &CONFIG.branding;
+    let product = &brand.product_name;
+    let link = format!("{}/revoke?jwt={}", CONFIG.sso_base_url, jwt);
+    let occurred_at = Utc::now().format("%Y-%m-%d %H:%M").to_string();
+    let (title, message) = describe(locale, product, event);
+
+    let (subject, plain, security_template) = match locale {
+        Locale::Fr => {
+            let ip = meta.ip.as_deref().unwrap_or("inconnue");
+            let device = meta.user_agent.as_deref().unwrap_or("inconnu");
+            (
+                format!("{product} - {}", title),
+                format!(
+                    "{product} - {} Depuis {} ({}) le {} utc. Si ce n'était pas vous, utilisez le lien suivant pour fermer toutes vos sessions : {}",
+                    message, ip, device, occurred_at, link
+                ),
+                render_template(
+                    "fr/security.stpl",
+                    SecurityTemplateFr {
+                        brand,
+                        title: &title,
+                        message: &message,
+                        ip,
+                        device,
+                        occurred_at: &occurred_at,
+                        link: &link,
+                    },
+                )?,
+            )
+        }
+        _ => {
+            let ip = meta.ip.as_deref().unwrap_or("unknown");
+            let device = meta.user_agent.as_deref().unwrap_or("unknown");
+            (
+                format!("{product} - {}", title),
+                format!(
+                    "{product} - {} From {} ({}) at {} utc. If it wasn't you, use the following link to sign out of all your sessions: {}",
+                    message, ip, device, occurred_at, link
+                ),
+                render_template(
+                    "security.stpl",
+                    SecurityTemplate {
+                        brand,
+                        title: &title,
+                        message: &message,
+                        ip,
+                        device,
+                        occurred_at: &occurred_at,
+                        link: &link,
+                    },
+                )?,
+            )
+        }
+    };
+
+    Ok(RenderedMail {
+        subject,
+        plain,
+        html: Some(security_template),
+        expires_in: Some(Duration::days(REVOKE_LINK_DAYS)),
+    })
+}
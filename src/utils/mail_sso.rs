@@ -1,121 +1,129 @@
-use chrono::Utc;
-use lettre::message::{Mailbox, MultiPart};
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::transport::smtp::client::{Tls, TlsParameters};
-use lettre::transport::smtp::PoolConfig;
-use lettre::SmtpTransport;
-use lettre::{
-    message::{header, SinglePart},
-    Message, Transport,
-};
-use once_cell::sync::Lazy;
+use chrono::{Duration, Utc};
+use lettre::message::Mailbox;
 use sailfish::TemplateOnce;
+use serde::Serialize;
 use sproot::apierrors::ApiError;
 
-use crate::CONFIG;
+use super::{
+    config::Branding,
+    i18n::Locale,
+    mailer::{send_mail, RenderedMail},
+    templates::render_template,
+};
+use crate::{ConnType, CONFIG};
 
-// Lazy static for SmtpTransport used to send mails
-// Build it using rustls and a pool of 16 items.
-static MAILER: Lazy<SmtpTransport> = Lazy::new(|| match get_smtp_transport() {
-    Ok(smtp) => smtp,
-    Err(err) => {
-        error!("MAILER: cannot get the smtp_transport: {}", err);
-        std::process::exit(1);
-    }
-});
+/// Validity of the link to create an account
+pub const REGISTER_LINK_MINUTES: i64 = 30;
 
-pub fn test_smtp_transport() {
-    // Check if the SMTP server host is "ok"
-    match MAILER.test_connection() {
-        Ok(result) => {
-            info!("MAILER: No fatal error, connect is: {}", result);
-        }
-        Err(e) => {
-            error!("MAILER: test of the smtp_transport failed: {}", e);
-            std::process::exit(1);
-        }
-    }
+/// Structure representing the incident template html sent by mail
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "sso.stpl")]
+struct SsoTemplate<'a> {
+    brand: &'a Branding,
+    sso_base: &'a str,
+    jwt: &'a str,
+    validity_minutes: i64,
 }
 
-fn get_smtp_transport() -> Result<SmtpTransport, lettre::transport::smtp::Error> {
-    let creds = Credentials::new(CONFIG.smtp_user.to_owned(), CONFIG.smtp_password.to_owned());
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "fr/sso.stpl")]
+struct SsoTemplateFr<'a> {
+    brand: &'a Branding,
+    sso_base: &'a str,
+    jwt: &'a str,
+    validity_minutes: i64,
+}
 
-    let transport = if CONFIG.smtp_tls {
-        SmtpTransport::builder_dangerous(&CONFIG.smtp_host).tls(Tls::Required(TlsParameters::new(
-            CONFIG.smtp_host.to_owned(),
-        )?))
-    } else {
-        SmtpTransport::builder_dangerous(&CONFIG.smtp_host)
-    };
+/// Structure representing the sign up template html sent by mail
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "register.stpl")]
+struct RegisterTemplate<'a> {
+    brand: &'a Branding,
+    link: &'a str,
+}
 
-    // Open a remote connection to gmail
-    Ok(transport
-        .port(CONFIG.smtp_port)
-        .credentials(creds)
-        .pool_config(PoolConfig::new().max_size(16))
-        .build())
+#[derive(TemplateOnce, Serialize)]
+#[template(path = "fr/register.stpl")]
+struct RegisterTemplateFr<'a> {
+    brand: &'a Branding,
+    link: &'a str,
 }
 
-fn send_mail(email_addr: Mailbox, template: String, jwt: &str) -> Result<(), ApiError> {
-    // Build the email with all params
-    let email = Message::builder()
-        // Sender is the email of the sender, which is used by the SMTP
-        // if the sender is not equals to the smtp server account, the mail will ends in the spam.
-        .from(CONFIG.smtp_email_sender.to_owned())
-        // Receiver is the person who should get the email
-        .to(email_addr)
-        .subject(format!("Speculare - Authentication Requested at {} (utc)", Utc::now().format("%H:%M:%S")))
-        .multipart(
-            // Use multipart to have a fallback
-        MultiPart::alternative()
-                // This singlepart is the fallback for the html code
-                // ==> Very basic.
-                .singlepart(
-                    SinglePart::builder()
-                    .header(header::ContentType::TEXT_PLAIN)
-                    .body(format!("Speculare - Passwordless Authentication. Use the following link to sign in on Speculare: {}/csso?jwt={}", CONFIG.sso_base_url, jwt))
-                )
-                // This singlepart is the html design with all fields replaced
-                // ==> Prettier, ...
-                .singlepart(
-                    SinglePart::builder()
-                    .header(header::ContentType::TEXT_HTML)
-                    .body(template)
-                )
-    ).unwrap();
+/// Send an email alerting that a new incident was created.
+pub fn send_sso_mail(
+    conn: &mut ConnType,
+    email: Mailbox,
+    locale: Locale,
+    jwt: &str,
+) -> Result<(), ApiError> {
+    send_mail(conn, email, render_sso_mail(locale, jwt)?)
+}
+
+/// Subject and bodies of the mail sent by send_sso_mail
+pub fn render_sso_mail(locale: Locale, jwt: &str) -> Result<RenderedMail, ApiError> {
+    let brand = &CONFIG.branding;
+    let product = &brand.product_name;
+    let sso_base = &CONFIG.sso_base_url;
+    let link = format!("{}/csso?jwt={}", sso_base, jwt);
+    let time = Utc::now().format("%H:%M:%S");
+    let validity_minutes = CONFIG.jwt.magic_link_minutes;
+
+    // Build the SsoTemplate (html code) in the customer's locale,
+    // the untranslated locales falling back to english.
+    let (subject, plain, sso_template) = match locale {
+        Locale::Fr => (
+            format!("{product} - Authentification demandée à {} (utc)", time),
+            format!("{product} - Authentification sans mot de passe. Utilisez le lien suivant pour vous connecter sur {product} (valable {} minutes) : {}", validity_minutes, link),
+            render_template("fr/sso.stpl", SsoTemplateFr { brand, sso_base, jwt, validity_minutes })?,
+        ),
+        _ => (
+            format!("{product} - Authentication Requested at {} (utc)", time),
+            format!("{product} - Passwordless Authentication. Use the following link to sign in on {product} (valid {} minutes): {}", validity_minutes, link),
+            render_template("sso.stpl", SsoTemplate { brand, sso_base, jwt, validity_minutes })?,
+        ),
+    };
 
-    // Send the email
-    match MAILER.send(&email) {
-        Ok(_) => Ok(()),
-        Err(err) => {
-            error!("Could not send email: {}", err);
-            Err(ApiError::ServerError(None))
-        }
-    }
+    Ok(RenderedMail {
+        subject,
+        plain,
+        html: Some(sso_template),
+        expires_in: Some(Duration::minutes(validity_minutes)),
+    })
 }
 
-/// Structure representing the incident template html sent by mail
-#[derive(TemplateOnce)]
-#[template(path = "sso.stpl")]
-struct SsoTemplate<'a> {
-    sso_base: &'a str,
-    jwt: &'a str,
+/// Send the link to create an account to an email without one
+pub fn send_register_mail(
+    conn: &mut ConnType,
+    email: Mailbox,
+    locale: Locale,
+    jwt: &str,
+) -> Result<(), ApiError> {
+    send_mail(conn, email, render_register_mail(locale, jwt)?)
 }
 
-/// Send an email alerting that a new incident was created.
-pub fn send_sso_mail(email: Mailbox, jwt: &str) -> Result<(), ApiError> {
-    // Build the SsoTemplate (html code)
-    // The SsoTemplate struct is used to hold all the information
-    // about the template, which values are needed, ...
-    let sso_template = SsoTemplate {
-        sso_base: &CONFIG.sso_base_url,
-        jwt,
-    }
-    .render_once()
-    .map_err(|err| {
-        error!("Could not build the email template: {}", err);
-        ApiError::ServerError(None)
-    })?;
+/// Subject and bodies of the mail sent by send_register_mail
+pub fn render_register_mail(locale: Locale, jwt: &str) -> Result<RenderedMail, ApiError> {
+    let brand = &CONFIG.branding;
+    let product = &brand.product_name;
+    let link = format!("{}/rsso?jwt={}", CONFIG.sso_base_url, jwt);
+
+    let (subject, plain, register_template) = match locale {
+        Locale::Fr => (
+            format!("{product} - Créez votre compte"),
+            format!("{product} - Vous avez tenté de vous connecter mais aucun compte n'existe pour cet email. Utilisez le lien suivant pour le créer : {}", link),
+            render_template("fr/register.stpl", RegisterTemplateFr { brand, link: &link })?,
+        ),
+        _ => (
+            format!("{product} - Create your account"),
+            format!("{product} - You tried to sign in but there's no account for this email. Use the following link to create it: {}", link),
+            render_template("register.stpl", RegisterTemplate { brand, link: &link })?,
+        ),
+    };
 
-    send_mail(email, sso_template, jwt)
+    Ok(RenderedMail {
+        subject,
+        plain,
+        html: Some(register_template),
+        expires_in: Some(Duration::minutes(REGISTER_LINK_MINUTES)),
+    })
 }
@@ -0,0 +1,332 @@
+use std::io::Write;
+
+use chrono::Duration;
+use lettre::message::dkim::{DkimConfig, DkimSigningAlgorithm, DkimSigningKey};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::PoolConfig;
+use lettre::{
+    message::{header, SinglePart},
+    Message, Transport,
+};
+use lettre::{FileTransport, SendmailTransport, SmtpTransport};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use sproot::apierrors::ApiError;
+
+use super::config::{DkimAlgorithm, MailTransportConfig};
+use crate::{
+    models::{QueuedMail, QueuedMailDTO},
+    ConnType, CONFIG,
+};
+
+/// Way of delivering the built mails (SMTP, sendmail, ...)
+pub trait MailTransport: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn send(&self, email: &Message) -> Result<(), String>;
+
+    /// Check that the transport is usable
+    fn test(&self) -> Result<(), String>;
+}
+
+impl MailTransport for SmtpTransport {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    fn send(&self, email: &Message) -> Result<(), String> {
+        Transport::send(self, email)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn test(&self) -> Result<(), String> {
+        match self.test_connection() {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("cannot connect to the smtp server".to_owned()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+/// Mails handed to the local sendmail binary
+struct Sendmail {
+    transport: SendmailTransport,
+    command: String,
+}
+
+impl MailTransport for Sendmail {
+    fn name(&self) -> &'static str {
+        "sendmail"
+    }
+
+    fn send(&self, email: &Message) -> Result<(), String> {
+        self.transport
+            .send(email)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn test(&self) -> Result<(), String> {
+        // A bare command is looked up in the PATH when sending
+        if self.command.contains('/') && !std::path::Path::new(&self.command).exists() {
+            return Err(format!("{} does not exist", self.command));
+        }
+        Ok(())
+    }
+}
+
+/// Mails written as .eml files in the directory
+struct EmlFiles {
+    transport: FileTransport,
+    dir: String,
+}
+
+impl MailTransport for EmlFiles {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn send(&self, email: &Message) -> Result<(), String> {
+        self.transport
+            .send(email)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn test(&self) -> Result<(), String> {
+        std::fs::create_dir_all(&self.dir).map_err(|err| format!("{}: {}", self.dir, err))
+    }
+}
+
+/// Mails printed to the standard output (local development)
+struct Stdout;
+
+impl MailTransport for Stdout {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn send(&self, email: &Message) -> Result<(), String> {
+        let mut stdout = std::io::stdout().lock();
+        stdout
+            .write_all(&email.formatted())
+            .and_then(|_| stdout.write_all(b"\n"))
+            .map_err(|err| err.to_string())
+    }
+
+    fn test(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Lazy static for the transport used to send mails, selected by
+// mail_transport (SMTP using rustls and a pool of 16 items by default).
+static MAILER: Lazy<Box<dyn MailTransport>> = Lazy::new(|| match get_transport() {
+    Ok(transport) => transport,
+    Err(err) => {
+        error!("MAILER: cannot get the mail transport: {}", err);
+        std::process::exit(1);
+    }
+});
+
+// Lazy static for the DKIM signature of the mails, if enabled
+static DKIM: Lazy<Option<DkimConfig>> = Lazy::new(|| {
+    CONFIG.dkim.as_ref().map(|dkim| match get_dkim_config() {
+        Ok(config) => config,
+        Err(err) => {
+            error!(
+                "MAILER: cannot load the dkim key {}: {}",
+                dkim.private_key, err
+            );
+            std::process::exit(1);
+        }
+    })
+});
+
+fn get_dkim_config() -> Result<DkimConfig, String> {
+    let dkim = CONFIG.dkim.as_ref().ok_or("dkim is not configured")?;
+    let key = std::fs::read_to_string(&dkim.private_key).map_err(|err| err.to_string())?;
+    let algorithm = match dkim.algorithm {
+        DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+        DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+    };
+    let signing_key = DkimSigningKey::new(key.trim(), algorithm).map_err(|err| err.to_string())?;
+
+    Ok(DkimConfig::default_config(
+        dkim.selector.to_owned(),
+        dkim.domain.to_owned(),
+        signing_key,
+    ))
+}
+
+/// Check that the dkim key (if any) parses, exiting otherwise
+pub fn test_dkim() {
+    let dkim = match &CONFIG.dkim {
+        Some(dkim) => dkim,
+        None => return,
+    };
+
+    Lazy::force(&DKIM);
+    info!(
+        "MAILER: mails signed using {}._domainkey.{}",
+        dkim.selector, dkim.domain
+    );
+    // The receivers only trust the signature of the sender's domain (alignment)
+    let sender = CONFIG.smtp_email_sender.email.domain();
+    if sender != dkim.domain && !sender.ends_with(&format!(".{}", dkim.domain)) {
+        warn!(
+            "MAILER: the dkim domain {} doesn't match the sender domain {}",
+            dkim.domain, sender
+        );
+    }
+}
+
+/// Check that the mails can be delivered. Only fatal for the
+/// SMTP transport, the others being used for development.
+pub fn test_mail_transport() {
+    match MAILER.test() {
+        Ok(_) => info!("MAILER: {} transport is ready", MAILER.name()),
+        Err(err) if MAILER.name() == "smtp" => {
+            error!("MAILER: test of the smtp transport failed: {}", err);
+            std::process::exit(1);
+        }
+        Err(err) => warn!(
+            "MAILER: test of the {} transport failed: {}",
+            MAILER.name(),
+            err
+        ),
+    }
+}
+
+fn get_transport() -> Result<Box<dyn MailTransport>, String> {
+    Ok(match &CONFIG.mail_transport {
+        MailTransportConfig::Smtp if CONFIG.smtp_host.is_empty() => {
+            return Err("'smtp_host' is required by the smtp transport".to_owned())
+        }
+        MailTransportConfig::Smtp => Box::new(get_smtp_transport().map_err(|err| err.to_string())?),
+        MailTransportConfig::Sendmail { command } => {
+            let command = command.to_owned().unwrap_or_else(|| "sendmail".to_owned());
+            Box::new(Sendmail {
+                transport: SendmailTransport::new_with_command(&command),
+                command,
+            })
+        }
+        MailTransportConfig::File { dir } => Box::new(EmlFiles {
+            transport: FileTransport::new(dir),
+            dir: dir.to_owned(),
+        }),
+        MailTransportConfig::Stdout => Box::new(Stdout),
+    })
+}
+
+fn get_smtp_transport() -> Result<SmtpTransport, lettre::transport::smtp::Error> {
+    let creds = Credentials::new(CONFIG.smtp_user.to_owned(), CONFIG.smtp_password.to_owned());
+
+    let transport = if CONFIG.smtp_tls {
+        SmtpTransport::builder_dangerous(&CONFIG.smtp_host).tls(Tls::Required(TlsParameters::new(
+            CONFIG.smtp_host.to_owned(),
+        )?))
+    } else {
+        SmtpTransport::builder_dangerous(&CONFIG.smtp_host)
+    };
+
+    // Open a remote connection to gmail
+    Ok(transport
+        .port(CONFIG.smtp_port)
+        .credentials(creds)
+        .pool_config(PoolConfig::new().max_size(16))
+        .build())
+}
+
+/// Subject and bodies of a mail, the html being the preferred
+/// alternative to the plain text if present.
+#[derive(Debug, Serialize)]
+pub struct RenderedMail {
+    pub subject: String,
+    pub plain: String,
+    pub html: Option<String>,
+    /// Lifetime of the link of the mail, it's not delivered once expired
+    #[serde(skip)]
+    pub expires_in: Option<Duration>,
+}
+
+/// Queue the mail to email_addr. The mail queue worker delivers
+/// it, so it's only sent if the transaction (if any) commits.
+pub fn send_mail(
+    conn: &mut ConnType,
+    email_addr: Mailbox,
+    mail: RenderedMail,
+) -> Result<(), ApiError> {
+    QueuedMail::enqueue(
+        conn,
+        &QueuedMailDTO {
+            recipient: &email_addr.to_string(),
+            subject: &mail.subject,
+            plain: &mail.plain,
+            html: mail.html.as_deref(),
+            expires_in_secs: mail.expires_in.map(|validity| validity.num_seconds()),
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Build and send a queued mail, returning the reason of the failure
+pub fn deliver(mail: &QueuedMail) -> Result<(), String> {
+    let email_addr: Mailbox = mail
+        .recipient
+        .parse()
+        .map_err(|err| format!("invalid recipient: {}", err))?;
+
+    let plain = mail.plain.as_deref().ok_or("the mail has no body")?;
+
+    send_message(email_addr, &mail.subject, plain, mail.html.as_deref())
+}
+
+/// Send the mail right away, bypassing the queue (used to test the transport)
+pub fn send_now(email_addr: Mailbox, mail: &RenderedMail) -> Result<(), String> {
+    send_message(email_addr, &mail.subject, &mail.plain, mail.html.as_deref())
+}
+
+fn send_message(
+    email_addr: Mailbox,
+    subject: &str,
+    plain: &str,
+    html: Option<&str>,
+) -> Result<(), String> {
+    // Build the email with all params
+    let builder = Message::builder()
+        // Sender is the email of the sender, which is used by the SMTP
+        // if the sender is not equals to the smtp server account, the mail will ends in the spam.
+        .from(CONFIG.smtp_email_sender.to_owned())
+        // Receiver is the person who should get the email
+        .to(email_addr)
+        .subject(subject);
+
+    // The plain text is the fallback for the html code (if any)
+    let plain = SinglePart::builder()
+        .header(header::ContentType::TEXT_PLAIN)
+        .body(plain.to_owned());
+    let mut email = match html {
+        Some(html) => builder.multipart(
+            MultiPart::alternative().singlepart(plain).singlepart(
+                SinglePart::builder()
+                    .header(header::ContentType::TEXT_HTML)
+                    .body(html.to_owned()),
+            ),
+        ),
+        None => builder.singlepart(plain),
+    }
+    .map_err(|err| format!("cannot build the email: {}", err))?;
+    if let Some(dkim) = DKIM.as_ref() {
+        email.sign(dkim);
+    }
+
+    // Send the email
+    MAILER
+        .send(&email)
+        .map_err(|err| format!("cannot send the email: {}", err))
+}
@@ -1,3 +1,19 @@
+pub mod audit;
+pub mod bounces;
 pub mod config;
+pub mod domain_policy;
+pub mod email;
+pub mod i18n;
 pub mod jwt;
+pub mod mail_deletion;
+pub mod mail_email_change;
+pub mod mail_export;
+pub mod mail_invite;
+pub mod mail_preview;
+pub mod mail_security;
 pub mod mail_sso;
+pub mod mailer;
+pub mod notifications;
+pub mod pow;
+pub mod rate_limit;
+pub mod templates;
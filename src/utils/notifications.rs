@@ -0,0 +1,143 @@
+use std::str::FromStr;
+
+use actix_web::web;
+use base64::Engine;
+use chrono::Duration;
+use lettre::message::Mailbox;
+use serde::{Deserialize, Serialize};
+use sproot::{apierrors::ApiError, models::AuthPool};
+use uuid::Uuid;
+
+use super::{
+    audit::RequestMeta,
+    jwt::{self, Purpose},
+    mail_security::send_security_mail,
+};
+use crate::models::{Account, RevokeLink};
+
+/// Validity of the link revoking the sessions, sent with each notification
+pub const REVOKE_LINK_DAYS: i64 = 7;
+
+/// Kind of the security notifications sent by mail to the customers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotificationKind {
+    NewLogin,
+    KeyCreate,
+    KeyDelete,
+    HostBind,
+    EmailChange,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::NewLogin => "new-login",
+            NotificationKind::KeyCreate => "key-create",
+            NotificationKind::KeyDelete => "key-delete",
+            NotificationKind::HostBind => "host-bind",
+            NotificationKind::EmailChange => "email-change",
+        }
+    }
+
+    /// The critical notifications are always sent, whatever the opt-outs
+    pub fn is_critical(&self) -> bool {
+        matches!(self, NotificationKind::EmailChange)
+    }
+}
+
+impl FromStr for NotificationKind {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "new-login" => Ok(NotificationKind::NewLogin),
+            "key-create" => Ok(NotificationKind::KeyCreate),
+            "key-delete" => Ok(NotificationKind::KeyDelete),
+            "host-bind" => Ok(NotificationKind::HostBind),
+            "email-change" => Ok(NotificationKind::EmailChange),
+            _ => Err(ApiError::InvalidRequestError(None)),
+        }
+    }
+}
+
+/// Event of the customer's account to notify him of
+#[derive(Debug, Clone)]
+pub enum SecurityEvent {
+    /// Session opened from a user agent never seen for the customer
+    NewLogin,
+    KeyCreate {
+        key_id: i64,
+    },
+    KeyDelete {
+        key_id: i64,
+    },
+    HostBind {
+        key_id: i64,
+        host_uuid: String,
+    },
+    /// Sent to the previous email, the new one having just been confirmed
+    EmailChange {
+        old_email: String,
+        new_email: String,
+    },
+}
+
+impl SecurityEvent {
+    pub fn kind(&self) -> NotificationKind {
+        match self {
+            SecurityEvent::NewLogin => NotificationKind::NewLogin,
+            SecurityEvent::KeyCreate { .. } => NotificationKind::KeyCreate,
+            SecurityEvent::KeyDelete { .. } => NotificationKind::KeyDelete,
+            SecurityEvent::HostBind { .. } => NotificationKind::HostBind,
+            SecurityEvent::EmailChange { .. } => NotificationKind::EmailChange,
+        }
+    }
+}
+
+/// Send the notification of the event to the customer (cid), unless he
+/// opted out of its kind. The action being already done, a failure to
+/// notify is only logged.
+pub async fn notify(db: &web::Data<AuthPool>, cid: Uuid, event: SecurityEvent, meta: &RequestMeta) {
+    let kind = event.kind();
+    let dbc = db.clone();
+    let meta = meta.clone();
+    let res = web::block(move || -> Result<(), ApiError> {
+        let conn = &mut dbc.pool.get()?;
+        let account = Account::get(conn, &cid)?;
+        if !account.wants_notification(kind) {
+            return Ok(());
+        }
+
+        // The link sent for an email change restores the previous email
+        let previous_email = match &event {
+            SecurityEvent::EmailChange { old_email, .. } => Some(old_email.as_str()),
+            _ => None,
+        };
+        let recipient = previous_email.unwrap_or(&account.email);
+        let mailboxed: Mailbox = recipient.parse().map_err(|_| ApiError::ServerError(None))?;
+
+        // The token carries the id of the link, which can only be used once
+        let link = RevokeLink::create(conn, &cid, previous_email, REVOKE_LINK_DAYS as i32)?;
+        let jwt = jwt::create_purpose_jwt(
+            Purpose::Revoke,
+            &link.id.to_string(),
+            Duration::days(REVOKE_LINK_DAYS),
+        )?;
+        send_security_mail(
+            conn,
+            mailboxed,
+            account.locale(),
+            &event,
+            &meta,
+            &base64::prelude::BASE64_STANDARD.encode(jwt),
+        )
+    })
+    .await;
+
+    match res {
+        Ok(Ok(_)) => {}
+        Ok(Err(err)) => error!("NOTIFY: cannot send {} to {}: {}", kind.as_str(), cid, err),
+        Err(err) => error!("NOTIFY: cannot send {} to {}: {}", kind.as_str(), cid, err),
+    }
+}
@@ -0,0 +1,267 @@
+use actix_web::{web, HttpRequest};
+use chrono::Duration;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sproot::{apierrors::ApiError, models::AuthPool};
+
+use super::{
+    config::PowConfig,
+    jwt::{self, JwtKeys, Purpose},
+};
+use crate::{api::get_header_value, models::RateCounter, CONFIG};
+
+// Validity of a challenge, which can only be solved once
+pub const CHALLENGE_VALIDITY_SECS: i32 = 300;
+
+/// Hashcash like challenge: find a solution such as the sha256 of
+/// `challenge:solution` starts with (at least) difficulty zero bits.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Challenge {
+    pub challenge: String,
+    pub difficulty: u32,
+}
+
+/// Difficulty of the challenge given the number of challenges
+/// recently requested from the same ip or for the same email.
+pub fn difficulty_for(volume: i32) -> u32 {
+    difficulty_with(volume, &CONFIG.pow)
+}
+
+/// Difficulty as difficulty_for computes it, using the given settings
+pub fn difficulty_with(volume: i32, pow: &PowConfig) -> u32 {
+    let extra = (volume.max(0) / pow.step.max(1)) as u32;
+
+    (pow.base_difficulty + extra).min(pow.max_difficulty)
+}
+
+/// Issue a signed (stateless) challenge, bound to the email if any
+pub fn issue(difficulty: u32, email: &str) -> Result<Challenge, ApiError> {
+    issue_with(&JwtKeys::from_config(), difficulty, email)
+}
+
+/// Issue the challenge as issue does, signed with the given keys
+pub fn issue_with(keys: &JwtKeys, difficulty: u32, email: &str) -> Result<Challenge, ApiError> {
+    let nonce: String = thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+
+    let challenge = jwt::create_purpose_jwt_with(
+        keys,
+        Purpose::Challenge,
+        &format!("{}:{}:{}", difficulty, nonce, email),
+        Duration::seconds(CHALLENGE_VALIDITY_SECS as i64),
+    )?;
+
+    Ok(Challenge {
+        challenge,
+        difficulty,
+    })
+}
+
+/// Number of leading zero bits of the hash
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+
+    bits
+}
+
+/// Check the `challenge:solution` and return the nonce of the challenge.
+/// If email is set, the challenge must have been issued for it.
+fn verify(keys: &JwtKeys, proof: &str, email: Option<&str>) -> Result<String, ApiError> {
+    let invalid = || ApiError::AuthorizationError(Some("pow_invalid".to_owned()));
+
+    // The challenge (jwt) can't contain a ':' but the solution could
+    let (challenge, _) = proof.split_once(':').ok_or_else(invalid)?;
+    let subject =
+        jwt::decode_purpose_jwt_with(keys, Purpose::Challenge, challenge).map_err(|_| invalid())?;
+
+    let mut parts = subject.splitn(3, ':');
+    let (difficulty, nonce, issued_for) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(difficulty), Some(nonce), Some(issued_for)) => (
+            difficulty.parse::<u32>().map_err(|_| invalid())?,
+            nonce,
+            issued_for,
+        ),
+        _ => return Err(invalid()),
+    };
+
+    if matches!(email, Some(email) if email != issued_for) {
+        return Err(invalid());
+    }
+    if leading_zero_bits(&Sha256::digest(proof.as_bytes())) < difficulty {
+        return Err(invalid());
+    }
+
+    Ok(nonce.to_owned())
+}
+
+/// Require a solved challenge in the SPPOW header (`challenge:solution`),
+/// unless the proof of work is disabled. Each challenge is only valid once.
+pub async fn check_pow(
+    request: &HttpRequest,
+    db: &web::Data<AuthPool>,
+    email: Option<&str>,
+) -> Result<(), ApiError> {
+    if !CONFIG.pow.enabled {
+        return Ok(());
+    }
+
+    let proof = get_header_value(request, "SPPOW")
+        .ok()
+        .and_then(|value| value.to_str().map(str::to_owned).ok())
+        .ok_or_else(|| ApiError::AuthorizationError(Some("pow_required".to_owned())))?;
+    let nonce = verify(&JwtKeys::from_config(), &proof, email)?;
+
+    // Refuse the replay of an already used challenge
+    let db = db.clone();
+    let counter = web::block(move || {
+        RateCounter::hit(
+            &mut db.pool.get()?,
+            &format!("pow:nonce:{}", nonce),
+            CHALLENGE_VALIDITY_SECS,
+        )
+    })
+    .await??;
+    if counter.count > 1 {
+        return Err(ApiError::AuthorizationError(Some("pow_invalid".to_owned())));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+
+    use super::*;
+
+    const SECRET: &[u8] = b"pow-test-secret";
+
+    fn with_keys<T>(test: impl FnOnce(&JwtKeys) -> T) -> T {
+        let encoding = EncodingKey::from_secret(SECRET);
+        let decoding = DecodingKey::from_secret(SECRET);
+        test(&JwtKeys {
+            algorithm: Algorithm::HS256,
+            encoding: &encoding,
+            decoding: &decoding,
+            issuer: "ssot",
+            audience: "tests",
+            leeway_secs: 0,
+        })
+    }
+
+    /// Find the `challenge:solution` proof of the challenge
+    fn solve(challenge: &Challenge) -> String {
+        (0u64..)
+            .map(|solution| format!("{}:{}", challenge.challenge, solution))
+            .find(|proof| {
+                leading_zero_bits(&Sha256::digest(proof.as_bytes())) >= challenge.difficulty
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn solved_challenge() {
+        with_keys(|keys| {
+            let challenge = issue_with(keys, 4, "jane@example.com").unwrap();
+            let proof = solve(&challenge);
+            assert!(verify(keys, &proof, Some("jane@example.com")).is_ok());
+            // The challenge is bound to the email, not to the route
+            assert!(verify(keys, &proof, None).is_ok());
+        });
+    }
+
+    #[test]
+    fn unsolved_challenge() {
+        with_keys(|keys| {
+            let challenge = issue_with(keys, 4, "").unwrap();
+            let proof = (0u64..)
+                .map(|solution| format!("{}:{}", challenge.challenge, solution))
+                .find(|proof| leading_zero_bits(&Sha256::digest(proof.as_bytes())) < 4)
+                .unwrap();
+            assert!(verify(keys, &proof, None).is_err());
+            assert!(verify(keys, "no-solution", None).is_err());
+        });
+    }
+
+    #[test]
+    fn wrong_email_binding() {
+        with_keys(|keys| {
+            let challenge = issue_with(keys, 4, "jane@example.com").unwrap();
+            let proof = solve(&challenge);
+            assert!(verify(keys, &proof, Some("john@example.com")).is_err());
+
+            // Nor can a challenge issued without email be used for one
+            let unbound = solve(&issue_with(keys, 4, "").unwrap());
+            assert!(verify(keys, &unbound, Some("jane@example.com")).is_err());
+        });
+    }
+
+    #[test]
+    fn expired_challenge() {
+        with_keys(|keys| {
+            let jwt = jwt::create_purpose_jwt_with(
+                keys,
+                Purpose::Challenge,
+                "4:nonce:jane@example.com",
+                Duration::seconds(-60),
+            )
+            .unwrap();
+            let proof = solve(&Challenge {
+                challenge: jwt,
+                difficulty: 4,
+            });
+            assert!(verify(keys, &proof, Some("jane@example.com")).is_err());
+        });
+    }
+
+    #[test]
+    fn other_purpose_token() {
+        with_keys(|keys| {
+            let jwt =
+                jwt::create_purpose_jwt_with(keys, Purpose::Sso, "4:nonce:", Duration::seconds(60))
+                    .unwrap();
+            let proof = solve(&Challenge {
+                challenge: jwt,
+                difficulty: 4,
+            });
+            assert!(verify(keys, &proof, None).is_err());
+        });
+    }
+
+    #[test]
+    fn difficulty_bounds() {
+        let pow = PowConfig {
+            base_difficulty: 16,
+            max_difficulty: 20,
+            step: 10,
+            ..PowConfig::default()
+        };
+        assert_eq!(difficulty_with(0, &pow), 16);
+        assert_eq!(difficulty_with(-5, &pow), 16);
+        assert_eq!(difficulty_with(9, &pow), 16);
+        assert_eq!(difficulty_with(10, &pow), 17);
+        assert_eq!(difficulty_with(35, &pow), 19);
+        assert_eq!(difficulty_with(i32::MAX, &pow), 20);
+
+        // A zero step is treated as one
+        let pow = PowConfig { step: 0, ..pow };
+        assert_eq!(difficulty_with(3, &pow), 19);
+    }
+
+    #[test]
+    fn zero_bits() {
+        assert_eq!(leading_zero_bits(&[0, 0, 0xff]), 16);
+        assert_eq!(leading_zero_bits(&[0, 0x10, 0]), 11);
+        assert_eq!(leading_zero_bits(&[0x80]), 0);
+    }
+}
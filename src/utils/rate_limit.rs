@@ -0,0 +1,72 @@
+use actix_web::{http::header, web, HttpResponse};
+use sproot::{apierrors::ApiError, models::AuthPool};
+
+use super::config::RateWindow;
+use crate::models::RateCounter;
+
+/// Limit of the number of requests of a key (route, kind, value) per window
+#[derive(Debug)]
+pub struct RateLimit {
+    key: String,
+    window: RateWindow,
+}
+
+impl RateLimit {
+    /// The key is built from the limited route (scope), the kind
+    /// of value it's keyed by (ip, email, customer) and the value.
+    pub fn new(scope: &str, kind: &str, value: impl AsRef<str>, window: RateWindow) -> Self {
+        Self {
+            key: format!("{}:{}:{}", scope, kind, value.as_ref()),
+            window,
+        }
+    }
+}
+
+/// A rate limit has been exceeded
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after: i32,
+}
+
+impl RateLimited {
+    /// 429 Too Many Requests with the Retry-After (seconds) header
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, self.retry_after.max(1).to_string()))
+            .finish()
+    }
+}
+
+/// Count the request against each of the limits and return the
+/// longest Retry-After if any of them is exceeded.
+pub async fn check_limits(
+    db: &web::Data<AuthPool>,
+    limits: Vec<RateLimit>,
+) -> Result<Option<RateLimited>, ApiError> {
+    let limits: Vec<RateLimit> = limits
+        .into_iter()
+        .filter(|limit| limit.window.max > 0)
+        .collect();
+    if limits.is_empty() {
+        return Ok(None);
+    }
+
+    let db = db.clone();
+    web::block(move || {
+        let conn = &mut db.pool.get()?;
+        let mut limited: Option<RateLimited> = None;
+        for limit in limits {
+            let counter = RateCounter::hit(conn, &limit.key, limit.window.window_secs)?;
+            if counter.count > limit.window.max {
+                trace!("RATE: {} exceeded ({})", limit.key, counter.count);
+                let retry_after = limited.map_or(counter.retry_after, |l| {
+                    l.retry_after.max(counter.retry_after)
+                });
+                limited = Some(RateLimited { retry_after });
+            }
+        }
+
+        Ok(limited)
+    })
+    .await?
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use sailfish::TemplateOnce;
+use serde::Serialize;
+use serde_json::Value;
+use sproot::apierrors::ApiError;
+
+use crate::CONFIG;
+
+/// Render the html of a mail template, path being relative to the templates
+/// directory (eg: fr/sso.stpl). If the templates_dir contains a file at the
+/// same path it's used instead of the compiled-in template.
+pub fn render_template<T: TemplateOnce + Serialize>(
+    path: &str,
+    template: T,
+) -> Result<String, ApiError> {
+    if let Some(dir) = &CONFIG.templates_dir {
+        let file = Path::new(dir).join(path);
+        if file.is_file() {
+            return render_override(&file, &template);
+        }
+    }
+
+    template.render_once().map_err(|err| {
+        error!("Could not build the email template: {}", err);
+        ApiError::ServerError(None)
+    })
+}
+
+/// Render the override file, replacing each `{{ name }}` (or
+/// `{{ brand.name }}`) by the html escaped value of the template.
+fn render_override<T: Serialize>(file: &Path, template: &T) -> Result<String, ApiError> {
+    let source = std::fs::read_to_string(file).map_err(|err| {
+        error!("Could not read the template {}: {}", file.display(), err);
+        ApiError::ServerError(None)
+    })?;
+    let values = serde_json::to_value(template).map_err(|err| {
+        error!("Could not serialize the template values: {}", err);
+        ApiError::ServerError(None)
+    })?;
+
+    let mut rendered = String::with_capacity(source.len());
+    let mut rest = source.as_str();
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        rendered.push_str(&rest[..start]);
+
+        let name = rest[start + 2..end].trim();
+        match name
+            .split('.')
+            .try_fold(&values, |value, key| value.get(key))
+        {
+            Some(Value::String(value)) => rendered.push_str(&escape_html(value)),
+            Some(Value::Null) => {}
+            Some(value) => rendered.push_str(&escape_html(&value.to_string())),
+            None => warn!(
+                "Unknown variable {} in the template {}",
+                name,
+                file.display()
+            ),
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,173 @@
+// Tables owned by the ssot, the shared ones (customers, apikeys) are
+// defined in sproot::models::schema and only redeclared here when the
+// ssot needs columns sproot doesn't know about.
+
+diesel::table! {
+    audit_logs (id) {
+        id -> Int8,
+        customer_id -> Nullable<Uuid>,
+        actor -> Text,
+        action -> Text,
+        target -> Nullable<Text>,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        success -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    customers (id) {
+        id -> Uuid,
+        email -> Varchar,
+        status -> Text,
+        plan -> Text,
+        status_changed_at -> Nullable<Timestamp>,
+        locale -> Nullable<Text>,
+        notification_optouts -> Array<Text>,
+        deliverability -> Text,
+        deliverability_reason -> Nullable<Text>,
+        deliverability_changed_at -> Nullable<Timestamp>,
+        suspended_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    apikeys (id) {
+        id -> Int8,
+        key -> Text,
+        host_uuid -> Nullable<Text>,
+        customer_id -> Uuid,
+        berta -> Text,
+        org_id -> Uuid,
+        disabled_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    organizations (id) {
+        id -> Uuid,
+        name -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    org_members (org_id, customer_id) {
+        org_id -> Uuid,
+        customer_id -> Uuid,
+        created_at -> Timestamp,
+        role -> Text,
+    }
+}
+
+diesel::table! {
+    org_invitations (id) {
+        id -> Uuid,
+        org_id -> Uuid,
+        email -> Varchar,
+        invited_by -> Uuid,
+        created_at -> Timestamp,
+        role -> Text,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    customer_sessions (id) {
+        id -> Uuid,
+        customer_id -> Uuid,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    bertas (name) {
+        name -> Text,
+        enabled -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    data_exports (id) {
+        id -> Uuid,
+        customer_id -> Uuid,
+        status -> Text,
+        archive -> Nullable<Bytea>,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        claimed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    email_changes (id) {
+        id -> Uuid,
+        customer_id -> Uuid,
+        new_email -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    rate_limits (key) {
+        key -> Text,
+        window_start -> Timestamp,
+        count -> Int4,
+    }
+}
+
+diesel::table! {
+    invite_codes (code) {
+        code -> Text,
+        created_by -> Text,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        max_uses -> Int4,
+        uses -> Int4,
+    }
+}
+
+diesel::table! {
+    mail_outbox (id) {
+        id -> Uuid,
+        recipient -> Text,
+        subject -> Text,
+        plain -> Nullable<Text>,
+        html -> Nullable<Text>,
+        status -> Text,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        sent_at -> Nullable<Timestamp>,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    revoke_links (id) {
+        id -> Uuid,
+        customer_id -> Uuid,
+        previous_email -> Nullable<Text>,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::joinable!(customer_sessions -> customers (customer_id));
+diesel::joinable!(org_members -> customers (customer_id));
+diesel::joinable!(org_members -> organizations (org_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    customer_sessions,
+    customers,
+    org_members,
+    organizations
+);